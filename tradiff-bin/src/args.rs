@@ -1,28 +1,174 @@
 
-use clap_derive::Parser;
+use anyhow::{bail, Result};
+use clap_derive::{Args, Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
 
 
 #[derive(Parser, Debug)]
 #[command(name = "tradiff")]
 #[command(author, version)]
-#[command(about = "Shows differences in entries between two weidu TRA files", long_about = None)]
+#[command(about = "Shows differences in entries between two weidu TRA files (or directories of TRA files)", long_about = None)]
+#[command(after_help = "With --reference, compares the reference against each of the files (or directories)")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
 
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub charsets: CharsetArgs,
+
+    /// Also compare the texts of the entries present in both files<br>
+    /// Useful when comparing two versions of the same language file
+    #[arg(long, short)]
+    pub text: bool,
+
+    /// Show the whole text of the added and removed entries<br>
+    /// By default the texts are cut to fit in the terminal width, when the output is a terminal
+    #[arg(long)]
+    pub full_text: bool,
+
+    /// How the result is displayed
+    #[arg(long, short, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
+    /// Which findings make tradiff exit with a non-zero code<br>
+    /// Exit codes are 1 for differences, 2 for syntax errors (or any other trouble) and 3 for warnings
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Category::Differences, Category::Errors])]
+    pub fail_on: Vec<Category>,
+
+    /// Reference file (or directory), compared with each of the other files (or directories)
+    #[arg(long, short)]
+    pub reference: Option<String>,
+
+    /// The files (or directories) to be compared<br>
+    /// Exactly two without `--reference`, the translations to check with `--reference`
+    #[arg(required = true)]
+    pub files: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Writes the entries of the reference that are missing in the translation, to be translated
+    Stub(StubArgs),
+    /// Formats TRA files: one entry per line, with the same spacing everywhere
+    Fmt(FmtArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct StubArgs {
+
+    #[command(flatten)]
+    pub charsets: CharsetArgs,
+
+    /// Put a `// TODO translate` comment before each entry
+    #[arg(long)]
+    pub todo: bool,
+
+    /// Write the entries to this new file instead of the standard output
+    #[arg(long, short, conflicts_with = "append")]
+    pub output: Option<String>,
+
+    /// Add the entries to the translation file, in id order among its entries, instead of writing them to the
+    /// standard output<br>
+    /// The rest of the file is kept as it is
+    #[arg(long, short)]
+    pub append: bool,
+
+    /// The file containing all the entries
+    pub reference: String,
+    /// The file where some entries are missing
+    pub translation: String,
+}
+
+#[derive(Args, Debug)]
+pub struct FmtArgs {
+    /// Charset of the files<br>
+    /// For the accepted values see https://encoding.spec.whatwg.org/#concept-encoding-get
+    #[arg(long, short)]
+    pub charset: Option<String>,
+
+    /// Sort the entries by id, the comments just before an entry and at the end of its line move with it
+    #[arg(long, short)]
+    pub sort: bool,
+
+    /// Write all the strings between ~, or ~~~~~ when they contain ~
+    #[arg(long)]
+    pub normalize_delimiters: bool,
+
+    /// Only check that the files are formatted, exit with 1 when one of them is not
+    #[arg(long, conflicts_with = "in_place")]
+    pub check: bool,
+
+    /// Replace the files with their formatted version instead of writing it to the standard output
+    #[arg(long, short)]
+    pub in_place: bool,
+
+    /// The files to format, only one when the result is written to the standard output
+    #[arg(required = true)]
+    pub files: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CharsetArgs {
     /// Charset to be used when reading both files<br>
     /// For the accepted values see https://encoding.spec.whatwg.org/#concept-encoding-get
     #[arg(long, short)]
     pub charset: Option<String>,
-    /// Charset to be used when reading the first file<br>
+    /// Charset to be used when reading the first file (or the reference)<br>
     /// For the accepted values see https://encoding.spec.whatwg.org/#concept-encoding-get
     #[arg(long, requires = "charset2", conflicts_with="charset")]
     pub charset1: Option<String>,
-    /// Charset to be used when reading the second file<br>
+    /// Charset to be used when reading the second file (or the translations)<br>
     /// For the accepted values see https://encoding.spec.whatwg.org/#concept-encoding-get
     #[arg(long, requires = "charset1", conflicts_with="charset")]
     pub charset2: Option<String>,
+}
+
+impl CharsetArgs {
+    /// The encodings for the first and the second file
+    pub fn encodings(&self) -> Result<(&'static Encoding, &'static Encoding)> {
+        let encodings = match (&self.charset, &self.charset1, &self.charset2) {
+            (None, None, None) => (encoding_rs::UTF_8, encoding_rs::UTF_8),
+            (Some(charset), None, None) => {
+                let same = encoding_for_label(charset)?;
+                (same, same)
+            },
+            (None, Some(charset1), Some(charset2)) => (encoding_for_label(charset1)?, encoding_for_label(charset2)?),
+            _ => bail!("Developer error, could not determine charset combination"),
+        };
+        Ok(encodings)
+    }
+}
+
+impl FmtArgs {
+    pub fn encoding(&self) -> Result<&'static Encoding> {
+        self.charset.as_deref().map_or(Ok(encoding_rs::UTF_8), encoding_for_label)
+    }
+}
+
+fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+    match Encoding::for_label(label.as_bytes()) {
+        Some(encoding) => Ok(encoding),
+        None => bail!("Invalid charset label {label}.\nSee https://encoding.spec.whatwg.org/#concept-encoding-get for valid values"),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Colored text
+    Human,
+    /// Structured report, for use by other programs
+    Json,
+}
 
-    /// The first file to be compared
-    pub file1: String,
-    /// The second file to be compared
-    pub file2: String,
+/// Kinds of findings in the report
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// Added, removed or different entries
+    Differences,
+    /// Duplicated entries or characters that could not be decoded with the charset
+    Warnings,
+    /// Syntax errors
+    Errors,
 }
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use encoding_rs::Encoding;
+use itertools::Itertools;
+
+use crate::report::{build_report, DirectoryReport, FileCache, PairReport, Summary};
+
+/// Result of matching the TRA files found in two directories
+#[derive(Debug, PartialEq)]
+pub struct FilePairing {
+    /// Relative paths in the first and the second directory
+    pub pairs: Vec<(PathBuf, PathBuf)>,
+    pub only_in_first: Vec<PathBuf>,
+    pub only_in_second: Vec<PathBuf>,
+    /// Groups of files of the first directory whose paths differ only by case, only the first one is paired
+    pub case_collisions_in_first: Vec<Vec<PathBuf>>,
+    /// Same for the second directory
+    pub case_collisions_in_second: Vec<Vec<PathBuf>>,
+}
+
+/// Lists the `.tra` files in a directory and its subdirectories, as paths relative to this directory
+pub fn list_tra_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut result = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        let dir = root.join(&relative_dir);
+        let entries = read_dir(&dir).with_context(|| format!("Could not read directory {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Could not read directory {}", dir.display()))?;
+            let relative = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(relative);
+            } else if relative.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tra")) {
+                result.push(relative);
+            }
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// Matches files by relative path, ignoring case (weidu on windows does not care about case).
+/// When several files of a directory differ only by case, the first one is paired and the collision is reported.
+pub fn pair_files(first: Vec<PathBuf>, second: Vec<PathBuf>) -> FilePairing {
+    let (first, case_collisions_in_first) = without_case_collisions(first);
+    let (second, case_collisions_in_second) = without_case_collisions(second);
+    let mut second_by_key = second.into_iter()
+        .map(|path| (pairing_key(&path), path))
+        .collect::<BTreeMap<_, _>>();
+    let mut pairs = Vec::new();
+    let mut only_in_first = Vec::new();
+    for path in first {
+        match second_by_key.remove(&pairing_key(&path)) {
+            Some(other) => pairs.push((path, other)),
+            None => only_in_first.push(path),
+        }
+    }
+    FilePairing {
+        pairs,
+        only_in_first,
+        only_in_second: second_by_key.into_values().collect(),
+        case_collisions_in_first,
+        case_collisions_in_second,
+    }
+}
+
+/// Keeps the first of the paths with the same pairing key, and returns the groups of paths sharing a key
+fn without_case_collisions(paths: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<Vec<PathBuf>>) {
+    let mut by_key = BTreeMap::<String, Vec<PathBuf>>::new();
+    for path in paths {
+        by_key.entry(pairing_key(&path)).or_default().push(path);
+    }
+    let mut kept = by_key.values().map(|group| group[0].clone()).collect::<Vec<_>>();
+    kept.sort();
+    let collisions = by_key.into_values().filter(|group| group.len() > 1).collect();
+    (kept, collisions)
+}
+
+fn pairing_key(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .join("/")
+}
+
+/// Compares two files or two directories, the files of the first one are kept in `cache`
+pub fn build_pair_report(cache: &mut FileCache, first: &str, first_charset: &'static Encoding,
+                         second: &str, second_charset: &'static Encoding,
+                         compare_texts: bool) -> Result<PairReport> {
+    match (Path::new(first).is_dir(), Path::new(second).is_dir()) {
+        (false, false) => Ok(PairReport::Files(
+            build_report(cache, first, first_charset, second, second_charset, compare_texts)?
+        )),
+        (true, true) => Ok(PairReport::Directories(
+            build_directory_report(cache, first, first_charset, second, second_charset, compare_texts)?
+        )),
+        _ => bail!("Can only compare two files or two directories ({first} and {second})"),
+    }
+}
+
+pub fn build_directory_report(cache: &mut FileCache, first_dir: &str, first_charset: &'static Encoding,
+                              second_dir: &str, second_charset: &'static Encoding,
+                              compare_texts: bool) -> Result<DirectoryReport> {
+    let (first_root, second_root) = (Path::new(first_dir), Path::new(second_dir));
+    let pairing = pair_files(list_tra_files(first_root)?, list_tra_files(second_root)?);
+
+    let files = pairing.pairs.iter()
+        .map(|(first, second)| build_report(
+            cache,
+            &first_root.join(first).to_string_lossy(), first_charset,
+            &second_root.join(second).to_string_lossy(), second_charset,
+            compare_texts,
+        ))
+        .collect::<Result<Vec<_>>>()?;
+
+    let to_strings = |paths: &[PathBuf]| paths.iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    let in_root = |root: &Path, groups: &[Vec<PathBuf>]| groups.iter()
+        .map(|group| group.iter().map(|path| root.join(path).to_string_lossy().to_string()).collect())
+        .collect::<Vec<_>>();
+    let mut case_collisions = in_root(first_root, &pairing.case_collisions_in_first);
+    case_collisions.extend(in_root(second_root, &pairing.case_collisions_in_second));
+    Ok(DirectoryReport {
+        first: first_dir.to_string(),
+        second: second_dir.to_string(),
+        added_files: to_strings(&pairing.only_in_second),
+        removed_files: to_strings(&pairing.only_in_first),
+        case_collisions,
+        summary: Summary::of(&files),
+        files,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use crate::directory::{pair_files, FilePairing};
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn pairs_files_ignoring_case() {
+        let pairing = pair_files(
+            paths(&["HLA.tra", "sub/Setup.TRA", "only_first.tra"]),
+            paths(&["hla.tra", "Sub/setup.tra", "only_second.tra"]),
+        );
+        assert_eq!(pairing, FilePairing {
+            pairs: vec![
+                (PathBuf::from("HLA.tra"), PathBuf::from("hla.tra")),
+                (PathBuf::from("sub/Setup.TRA"), PathBuf::from("Sub/setup.tra")),
+            ],
+            only_in_first: paths(&["only_first.tra"]),
+            only_in_second: paths(&["only_second.tra"]),
+            case_collisions_in_first: vec![],
+            case_collisions_in_second: vec![],
+        });
+    }
+
+    #[test]
+    fn same_name_in_different_directories_is_not_paired() {
+        let pairing = pair_files(paths(&["a/x.tra"]), paths(&["b/x.tra"]));
+        assert_eq!(pairing, FilePairing {
+            pairs: vec![],
+            only_in_first: paths(&["a/x.tra"]),
+            only_in_second: paths(&["b/x.tra"]),
+            case_collisions_in_first: vec![],
+            case_collisions_in_second: vec![],
+        });
+    }
+
+    #[test]
+    fn files_differing_only_by_case_are_reported() {
+        let pairing = pair_files(
+            paths(&["setup.tra"]),
+            paths(&["SETUP.tra", "other.tra", "setup.tra"]),
+        );
+        assert_eq!(pairing, FilePairing {
+            pairs: vec![(PathBuf::from("setup.tra"), PathBuf::from("SETUP.tra"))],
+            only_in_first: vec![],
+            only_in_second: paths(&["other.tra"]),
+            case_collisions_in_first: vec![],
+            case_collisions_in_second: vec![paths(&["SETUP.tra", "setup.tra"])],
+        });
+    }
+}
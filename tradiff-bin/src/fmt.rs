@@ -0,0 +1,63 @@
+use std::fs::{read, write};
+
+use anyhow::{bail, Context, Result};
+use nu_ansi_term::Color;
+use tradiff_lib::{format_trafile, Diagnostic, FormatOptions};
+
+use crate::args::{Category, FmtArgs};
+use crate::outcome::SUCCESS;
+use crate::stub::encode;
+
+pub fn run_fmt(args: &FmtArgs) -> Result<u8> {
+    let charset = args.encoding()?;
+    if !args.check && !args.in_place && args.files.len() > 1 {
+        bail!("Only one file can be formatted to the standard output, use --in-place or --check for several files");
+    }
+    let options = FormatOptions { sort: args.sort, normalize_delimiters: args.normalize_delimiters };
+
+    let mut failed = false;
+    let mut unformatted = 0;
+    for path in &args.files {
+        let bytes = read(path).with_context(|| format!("Could not read {path}"))?;
+        let (content, replacements) = charset.decode_without_bom_handling(&bytes);
+        if replacements {
+            // writing the file back would replace the characters that could not be decoded
+            eprintln!("💥 {} {path} contains characters that can not be decoded with the {} charset",
+                      Color::Red.paint("ERROR"), charset.name());
+            failed = true;
+            continue;
+        }
+        let formatted = match format_trafile(&content, options) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                eprintln!("💥 {} {path} contains syntax errors and was not formatted", Color::Red.paint("ERROR"));
+                for error in &errors {
+                    eprintln!("{}\n", Diagnostic::from_error(error).render(&content, path));
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if args.check {
+            if formatted != content {
+                println!("{path} is not formatted");
+                unformatted += 1;
+            }
+        } else if args.in_place {
+            if formatted != content {
+                write(path, encode(&formatted, charset, path)?).with_context(|| format!("Could not write {path}"))?;
+                eprintln!("{path} formatted");
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+
+    if failed {
+        Ok(Category::Errors.exit_code())
+    } else if unformatted > 0 {
+        Ok(Category::Differences.exit_code())
+    } else {
+        Ok(SUCCESS)
+    }
+}
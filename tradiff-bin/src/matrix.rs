@@ -0,0 +1,30 @@
+use std::fs::canonicalize;
+
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+
+use crate::directory::build_pair_report;
+use crate::report::{FileCache, MatrixReport, TranslationCounts, TranslationReport};
+
+/// Compares the reference with each translation.
+/// The reference itself is skipped if it is among the translations (as with `tradiff -r lang/english lang/*`).
+/// The reference files are read and parsed only once.
+pub fn build_matrix_report(reference: &str, reference_charset: &'static Encoding,
+                           translations: &[String], translation_charset: &'static Encoding,
+                           compare_texts: bool) -> Result<MatrixReport> {
+    let reference_path = canonicalize(reference).with_context(|| format!("Could not find the reference {reference}"))?;
+    let mut cache = FileCache::default();
+    let translations = translations.iter()
+        .filter(|translation| canonicalize(translation).ok().as_ref() != Some(&reference_path))
+        .map(|translation| {
+            let details = build_pair_report(&mut cache, reference, reference_charset,
+                                            translation, translation_charset, compare_texts)?;
+            Ok(TranslationReport {
+                path: translation.clone(),
+                counts: TranslationCounts::of(&details),
+                details,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(MatrixReport { reference: reference.to_string(), translations })
+}
@@ -0,0 +1,116 @@
+use itertools::Itertools;
+
+use crate::args::Category;
+use crate::report::{DirectoryReport, MatrixReport, PairReport, Report};
+
+/// Exit code when nothing counting as a failure was found
+pub const SUCCESS: u8 = 0;
+
+impl Category {
+    /// Exit code following the `diff` convention (1 for differences, 2 for trouble), 3 for warnings
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Category::Differences => 1,
+            Category::Errors => 2,
+            Category::Warnings => 3,
+        }
+    }
+
+    /// Most severe first
+    fn severity(self) -> u8 {
+        match self {
+            Category::Errors => 2,
+            Category::Differences => 1,
+            Category::Warnings => 0,
+        }
+    }
+}
+
+impl Report {
+    /// The categories of findings present in the report
+    pub fn categories(&self) -> Vec<Category> {
+        let mut categories = Vec::new();
+        let files = [&self.first, &self.second];
+        if files.iter().any(|file| file.parse_failed || !file.errors.is_empty()) {
+            categories.push(Category::Errors);
+        }
+        if let Some(comparison) = &self.comparison {
+            let text_changed = comparison.text_changes.as_ref().is_some_and(|changes| !changes.is_empty());
+            if !comparison.added.is_empty() || !comparison.removed.is_empty()
+                    || !comparison.structure_mismatches.is_empty() || text_changed {
+                categories.push(Category::Differences);
+            }
+        }
+        if files.iter().any(|file| file.charset_replacements || !file.duplicates.is_empty()
+                || !file.unclosed_strings.is_empty()) {
+            categories.push(Category::Warnings);
+        }
+        categories
+    }
+}
+
+impl DirectoryReport {
+    /// The categories of findings present in any of the compared files,
+    /// a file missing in one of the directories is a difference, files differing only by case are a warning
+    pub fn categories(&self) -> Vec<Category> {
+        let missing_files = !self.added_files.is_empty() || !self.removed_files.is_empty();
+        self.files.iter()
+            .flat_map(Report::categories)
+            .chain(missing_files.then_some(Category::Differences))
+            .chain((!self.case_collisions.is_empty()).then_some(Category::Warnings))
+            .unique()
+            .collect()
+    }
+}
+
+impl PairReport {
+    pub fn categories(&self) -> Vec<Category> {
+        match self {
+            PairReport::Files(report) => report.categories(),
+            PairReport::Directories(report) => report.categories(),
+        }
+    }
+}
+
+impl MatrixReport {
+    /// The categories of findings present in any of the translations
+    pub fn categories(&self) -> Vec<Category> {
+        self.translations.iter()
+            .flat_map(|translation| translation.details.categories())
+            .unique()
+            .collect()
+    }
+}
+
+/// The exit code of the most severe category found among those counting as failures
+pub fn exit_code(found: &[Category], fail_on: &[Category]) -> u8 {
+    found.iter()
+        .filter(|category| fail_on.contains(category))
+        .max_by_key(|category| category.severity())
+        .map(|category| category.exit_code())
+        .unwrap_or(SUCCESS)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::args::Category::*;
+    use crate::outcome::{exit_code, SUCCESS};
+
+    #[test]
+    fn nothing_found() {
+        assert_eq!(exit_code(&[], &[Differences, Warnings, Errors]), SUCCESS);
+    }
+
+    #[test]
+    fn only_selected_categories_fail() {
+        assert_eq!(exit_code(&[Warnings], &[Differences, Errors]), SUCCESS);
+        assert_eq!(exit_code(&[Warnings], &[Warnings]), 3);
+        assert_eq!(exit_code(&[Differences, Warnings], &[Warnings]), 3);
+    }
+
+    #[test]
+    fn most_severe_category_wins() {
+        assert_eq!(exit_code(&[Differences, Warnings], &[Differences, Warnings, Errors]), 1);
+        assert_eq!(exit_code(&[Errors, Differences, Warnings], &[Differences, Warnings, Errors]), 2);
+    }
+}
@@ -0,0 +1,343 @@
+use itertools::Itertools;
+use nu_ansi_term::Color;
+use serde::Serialize;
+use tradiff_lib::{EntryKind, LinePosition};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::report::{Comparison, DirectoryReport, FileReport, ListedEntry, MatrixReport, PairReport, Kind, Report, StructureDifference, TextChangeReport};
+use crate::text_diff::paint_segments;
+
+const ORANGE: Color = Color::Rgb(255, 165, 0);
+
+/// How the report is printed for humans
+#[derive(Debug, Clone, Copy)]
+pub struct HumanOptions {
+    /// Width of the rulers between the parts of the report
+    pub term_width: usize,
+    /// Width the texts of the added and removed entries are cut at, `None` prints the whole texts
+    pub text_width: Option<usize>,
+}
+
+/// Prints the report for humans, with colors and emojis
+pub fn print_human(report: &Report, options: HumanOptions) {
+    print_file_problems(&report.first, "first");
+    print_file_problems(&report.second, "second");
+
+    let found_dups = !report.first.duplicates.is_empty() || !report.second.duplicates.is_empty();
+
+    if found_dups { println!("\n{}", ORANGE.paint("━".repeat(options.term_width))) }
+    print_duplicates(&report.first, "first");
+    print_duplicates(&report.second, "second");
+    if found_dups { println!("{}\n", ORANGE.paint("━".repeat(options.term_width))) }
+
+    if let Some(comparison) = &report.comparison {
+        print_comparison(comparison, options);
+    }
+}
+
+/// Prints the report of each pair of files, then the files found in only one directory and the totals
+pub fn print_directory_human(report: &DirectoryReport, options: HumanOptions) {
+    for file_report in &report.files {
+        if file_report.categories().is_empty() {
+            println!("✅ {} and {} contain the same entries.", file_report.first.path, file_report.second.path);
+            continue;
+        }
+        println!("\n{} {} ↔ {}", Color::Blue.bold().paint("▶"), file_report.first.path, file_report.second.path);
+        print_human(file_report, options);
+    }
+
+    println!("{}", "━".repeat(options.term_width));
+    if !report.added_files.is_empty() {
+        println!("{} Files in the second directory ({}) but not in the first directory:\n  - {}",
+                Color::Green.bold().paint("+"),
+                report.second, report.added_files.iter().join("\n  - "));
+    }
+    if !report.removed_files.is_empty() {
+        println!("{} Files in the first directory ({}) but not in the second directory:\n  - {}",
+                Color::Red.bold().paint("−"),
+                report.first, report.removed_files.iter().join("\n  - "));
+    }
+    if !report.case_collisions.is_empty() {
+        println!("🚨 {} Files differing only by case, only the first one of each group is compared:\n  - {}",
+                ORANGE.paint("WARN"),
+                report.case_collisions.iter().map(|group| group.join(", ")).join("\n  - "));
+    }
+    let summary = &report.summary;
+    println!("{} files compared, {} with differences\n  \
+              {} added entries, {} removed entries, {} structure mismatches, {} text changes\n  \
+              {} duplicated entries, {} syntax errors, {} files could not be parsed",
+            summary.compared_files, summary.files_with_differences,
+            summary.added_entries, summary.removed_entries, summary.structure_mismatches, summary.text_changes,
+            summary.duplicated_entries, summary.syntax_errors, summary.parse_failures);
+}
+
+pub fn print_pair_human(report: &PairReport, options: HumanOptions) {
+    match report {
+        PairReport::Files(report) => print_human(report, options),
+        PairReport::Directories(report) => print_directory_human(report, options),
+    }
+}
+
+/// Prints the details for each translation, then a table with the counts for all translations
+pub fn print_matrix_human(report: &MatrixReport, options: HumanOptions) {
+    for translation in &report.translations {
+        println!("\n{} {} ↔ {}", Color::Blue.bold().paint("▶▶"), report.reference, translation.path);
+        print_pair_human(&translation.details, options);
+    }
+
+    let with_files = report.translations.iter()
+        .any(|translation| matches!(translation.details, PairReport::Directories(_)));
+    let mut header = vec!["Translation", "Missing", "Extra", "Duplicated", "Structure", "Errors"];
+    if with_files {
+        header.extend(["Missing files", "Extra files"]);
+    }
+    let mut rows = vec![header.into_iter().map(str::to_string).collect::<Vec<_>>()];
+    for translation in &report.translations {
+        let counts = &translation.counts;
+        let mut row = vec![
+            translation.path.clone(),
+            counts.missing_entries.to_string(),
+            counts.extra_entries.to_string(),
+            counts.duplicated_entries.to_string(),
+            counts.structure_mismatches.to_string(),
+            counts.syntax_errors.to_string(),
+        ];
+        if with_files {
+            row.extend([counts.missing_files.to_string(), counts.extra_files.to_string()]);
+        }
+        rows.push(row);
+    }
+
+    println!("{}", "━".repeat(options.term_width));
+    println!("Compared with {}", report.reference);
+    let lines = format_table(&rows);
+    if let Some((header, body)) = lines.split_first() {
+        println!("{}", Color::Default.bold().paint(header));
+        body.iter().for_each(|line| println!("{line}"));
+    }
+}
+
+/// Aligns the cells in columns, the first column to the left and the other ones to the right
+fn format_table(rows: &[Vec<String>]) -> Vec<String> {
+    let column_count = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths = (0..column_count)
+        .map(|column| rows.iter()
+            .filter_map(|row| row.get(column))
+            .map(|cell| cell.chars().count())
+            .max()
+            .unwrap_or(0))
+        .collect::<Vec<_>>();
+    rows.iter()
+        .map(|row| row.iter().zip(&widths).enumerate()
+            .map(|(column, (cell, width))| if column == 0 {
+                format!("{cell:<width$}")
+            } else {
+                format!("{cell:>width$}")
+            })
+            .join("  ")
+            .trim_end()
+            .to_string())
+        .collect()
+}
+
+/// Prints the report as JSON
+pub fn print_json<T: Serialize>(report: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(report)?);
+    Ok(())
+}
+
+fn print_file_problems(file: &FileReport, qualifier: &str) {
+    let path = &file.path;
+    if file.charset_replacements {
+        println!("🚨 {} The {qualifier} file ({path}) contains characters that could not be handled properly (replaced with �)",
+                ORANGE.paint("WARN"));
+    }
+    if !file.unclosed_strings.is_empty() {
+        println!("🚨 {} The {qualifier} file ({path}) contains strings that are probably not closed\n\n{}\n",
+                ORANGE.paint("WARN"), file.unclosed_strings.iter().map(|warning| &warning.rendered).join("\n\n"));
+    }
+    if file.parse_failed {
+        println!("🚨 {} Failed to parse the {qualifier} file ({path})\n\n{}\n",
+                Color::Red.paint("ERROR"), file.errors.iter().map(|error| &error.rendered).join("\n\n"));
+    } else if !file.errors.is_empty() {
+        println!("🚨 {} The {qualifier} file ({path}) contains syntax errors\n\n{}\n",
+                Color::Red.paint("ERROR"), file.errors.iter().map(|error| &error.rendered).join("\n\n"));
+    }
+    if !file.damaged_entries.is_empty() {
+        println!("🚨 {} Entries of the {qualifier} file ({path}) skipped because of syntax errors: {}",
+                Color::Red.paint("ERROR"), file.damaged_entries.iter().join(", "));
+    }
+}
+
+fn print_duplicates(file: &FileReport, qualifier: &str) {
+    let (conflicting, identical): (Vec<_>, Vec<_>) = file.duplicates.iter()
+        .partition(|duplicate| duplicate.conflicting);
+    if !conflicting.is_empty() {
+        println!("🚨 {} The {qualifier} file ({}) contains duplicated entries with different contents \
+                  (weidu keeps the last one)\n  - {}",
+                ORANGE.paint("WARN"),
+                file.path, conflicting.iter().map(|duplicate| format!("{}\n      {}",
+                    duplicate.id,
+                    duplicate.occurrences.iter()
+                        .map(|occurrence| format!("{}: {}",
+                            describe_line(&occurrence.position), occurrence.content.replace('\n', "\n        ")))
+                        .join("\n      "),
+                )).join("\n  - "))
+    }
+    if !identical.is_empty() {
+        println!("🚨 {} The {qualifier} file ({}) contains entries repeated with the same content\n  - {}",
+                ORANGE.paint("WARN"),
+                file.path, identical.iter().map(|duplicate| format!("{} at lines {}",
+                    duplicate.id,
+                    duplicate.occurrences.iter()
+                        .map(|occurrence| occurrence.position.as_ref()
+                            .map_or("?".to_string(), |position| position.line.to_string()))
+                        .join(", "),
+                )).join("\n  - "))
+    }
+}
+
+fn describe_line(position: &Option<LinePosition>) -> String {
+    match position {
+        Some(position) => format!("line {}", position.line),
+        None => "unknown line".to_string(),
+    }
+}
+
+fn print_comparison(comparison: &Comparison, options: HumanOptions) {
+    if comparison.added.is_empty() && comparison.removed.is_empty() {
+        println!("✅ Both files contain the same entries.");
+    }
+    if !comparison.added.is_empty() {
+        println!("{} Entries in the second file but not in the first file:\n  - {}",
+                Color::Green.bold().paint("+"),
+                comparison.added.iter().map(|entry| describe_listed_entry(entry, options)).join("\n  - "));
+    }
+    if !comparison.removed.is_empty() {
+        println!("{} Entries in the first file but not in the second file:\n  - {}",
+                Color::Red.bold().paint("−"),
+                comparison.removed.iter().map(|entry| describe_listed_entry(entry, options)).join("\n  - "));
+    }
+    if !comparison.structure_mismatches.is_empty() {
+        println!("{} Entries with a different structure in both files:\n  - {}",
+                ORANGE.bold().paint("≠"),
+                comparison.structure_mismatches.iter().map(|mismatch|
+                    format!("{}: {}", mismatch.id, mismatch.differences.iter().map(describe_difference).join(", "))
+                ).join("\n  - "));
+    }
+    if let Some(text_changes) = &comparison.text_changes {
+        print_text_changes(text_changes);
+    }
+    println!("\n");
+}
+
+/// The id of the entry with its line, sound reference, female variant and text, then its comments on the next lines
+fn describe_listed_entry(entry: &ListedEntry, options: HumanOptions) -> String {
+    let mut details = vec![describe_line(&entry.position)];
+    details.extend(entry.sound.as_ref().map(|sound| format!("sound {sound}")));
+    if entry.female_variant {
+        details.push("female variant".to_string());
+    }
+    let header = format!("{} ({}): ", entry.id, details.join(", "));
+    let text = match options.text_width {
+        None => entry.text.replace('\n', "\n      "),
+        // the header comes after `  - `
+        Some(width) => truncate(&entry.text.lines().join(" "), width.saturating_sub(4 + header.width())),
+    };
+    std::iter::once(format!("{header}{text}"))
+        .chain(entry.comments.iter()
+            .map(|comment| Color::DarkGray.paint(format!("// {}", comment.lines().map(str::trim).join("\n      // "))).to_string()))
+        .join("\n      ")
+}
+
+/// Keeps the first characters of the text so that it fits in `width` terminal columns, `…` marks the cut.
+/// Wide characters (like the CJK ones) take two columns.
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    // `…` takes one column
+    let Some(available) = width.checked_sub(1) else {
+        return String::new();
+    };
+    let mut used = 0;
+    let kept = text.chars()
+        .take_while(|c| {
+            used += c.width().unwrap_or(0);
+            used <= available
+        })
+        .collect::<String>();
+    kept + "…"
+}
+
+fn print_text_changes(changes: &[TextChangeReport]) {
+    if changes.is_empty() {
+        println!("✅ Entries present in both files have the same text.");
+        return;
+    }
+    println!("{} Entries with a different text:", Color::Yellow.bold().paint("✎"));
+    for change in changes {
+        println!("  @{}{}\n    {} {}\n    {} {}",
+                change.id, if change.female { " (female)" } else { "" },
+                Color::Red.bold().paint("−"),
+                paint_segments(&change.old, Color::Red).replace('\n', "\n      "),
+                Color::Green.bold().paint("+"),
+                paint_segments(&change.new, Color::Green).replace('\n', "\n      "));
+    }
+}
+
+fn describe_kind(kind: &Kind) -> String {
+    EntryKind::from(*kind).to_string()
+}
+
+fn describe_difference(difference: &StructureDifference) -> String {
+    fn only_in(in_first: bool) -> &'static str {
+        if in_first { "only in the first file" } else { "only in the second file" }
+    }
+    match difference {
+        StructureDifference::Kind { first, second } =>
+            format!("{} in the first file, {} in the second file", describe_kind(first), describe_kind(second)),
+        StructureDifference::Sound { first, .. } => format!("sound reference {}", only_in(*first)),
+        StructureDifference::FemaleVariant { first, .. } => format!("female variant {}", only_in(*first)),
+        StructureDifference::FemaleSound { first, .. } => format!("female sound reference {}", only_in(*first)),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::render::{format_table, truncate};
+
+    fn row(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let rows = vec![
+            row(&["Translation", "Missing", "Extra"]),
+            row(&["language/french", "12", "0"]),
+            row(&["language/español", "3", "1234567"]),
+        ];
+        assert_eq!(format_table(&rows), vec![
+            "Translation       Missing    Extra",
+            "language/french        12        0",
+            "language/español        3  1234567",
+        ]);
+    }
+
+    #[test]
+    fn texts_are_cut_at_the_width() {
+        assert_eq!(truncate("Greetings, traveller.", 30), "Greetings, traveller.");
+        assert_eq!(truncate("Greetings, traveller.", 12), "Greetings, …");
+        assert_eq!(truncate("Greetings, traveller.", 2), "G…");
+        assert_eq!(truncate("Greetings, traveller.", 1), "…");
+        assert_eq!(truncate("Greetings, traveller.", 0), "");
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(truncate("こんにちは", 10), "こんにちは");
+        assert_eq!(truncate("こんにちは", 9), "こんにち…");
+        assert_eq!(truncate("こんにちは", 8), "こんに…");
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::fs::read;
+
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use itertools::Itertools;
+use serde::Serialize;
+use tradiff_lib::{compare_shapes, find_unclosed_strings, Diagnostic, EntryComments, EntryKind, LinePosition, LoadError, ShapeMismatch, TraEntryContent, TraError, TraFile};
+
+use crate::args::Category;
+use crate::text_diff::{word_diff, Segment};
+
+/// Result of the comparison of two TRA files, independent of the way it is displayed
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub first: FileReport,
+    pub second: FileReport,
+    /// `None` when one of the files could not be parsed
+    pub comparison: Option<Comparison>,
+}
+
+/// Comparison of two files or of two directories
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum PairReport {
+    Files(Report),
+    Directories(DirectoryReport),
+}
+
+/// Comparison of a reference file (or directory) with several translations
+#[derive(Debug, Serialize)]
+pub struct MatrixReport {
+    pub reference: String,
+    pub translations: Vec<TranslationReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranslationReport {
+    pub path: String,
+    pub counts: TranslationCounts,
+    pub details: PairReport,
+}
+
+/// What is wrong in a translation, relative to the reference
+#[derive(Debug, Default, Serialize)]
+pub struct TranslationCounts {
+    /// Entries in the reference but not in the translation
+    pub missing_entries: usize,
+    /// Entries in the translation but not in the reference
+    pub extra_entries: usize,
+    /// Duplicated entries in the translation
+    pub duplicated_entries: usize,
+    pub structure_mismatches: usize,
+    /// Syntax errors in the translation
+    pub syntax_errors: usize,
+    /// Files in the reference directory but not in the translation directory
+    pub missing_files: usize,
+    /// Files in the translation directory but not in the reference directory
+    pub extra_files: usize,
+}
+
+/// Result of the comparison of the TRA files of two directories
+#[derive(Debug, Serialize)]
+pub struct DirectoryReport {
+    pub first: String,
+    pub second: String,
+    /// Files in the second directory but not in the first one (relative paths)
+    pub added_files: Vec<String>,
+    /// Files in the first directory but not in the second one (relative paths)
+    pub removed_files: Vec<String>,
+    /// Groups of files of the same directory whose paths differ only by case, only the first one is compared
+    pub case_collisions: Vec<Vec<String>>,
+    /// Comparison of each pair of files with the same relative path
+    pub files: Vec<Report>,
+    pub summary: Summary,
+}
+
+/// Totals over all the compared pairs of files
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub compared_files: usize,
+    pub files_with_differences: usize,
+    pub added_entries: usize,
+    pub removed_entries: usize,
+    pub structure_mismatches: usize,
+    pub text_changes: usize,
+    pub duplicated_entries: usize,
+    pub syntax_errors: usize,
+    pub parse_failures: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    /// Some characters could not be decoded with the charset and were replaced with �
+    pub charset_replacements: bool,
+    /// The file could not be parsed at all
+    pub parse_failed: bool,
+    pub errors: Vec<SyntaxError>,
+    /// Strings that are probably missing their closing delimiter (warnings)
+    pub unclosed_strings: Vec<SyntaxError>,
+    /// Ids of the entries skipped because of a syntax error
+    pub damaged_entries: Vec<i64>,
+    pub duplicates: Vec<Duplicate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyntaxError {
+    /// Identifies the kind of error, like `E010`
+    pub code: &'static str,
+    pub message: String,
+    pub position: Option<LinePosition>,
+    pub hint: Option<String>,
+    /// The diagnostic with the source line, for humans
+    #[serde(skip)]
+    pub rendered: String,
+}
+
+/// An id defined several times in the same file
+#[derive(Debug, Clone, Serialize)]
+pub struct Duplicate {
+    pub id: i64,
+    /// The occurrences do not all have the same content, weidu silently keeps the last one
+    pub conflicting: bool,
+    /// In file order
+    pub occurrences: Vec<Occurrence>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Occurrence {
+    pub position: Option<LinePosition>,
+    /// The content of the entry, in TRA syntax
+    pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    /// Entries in the second file but not in the first file
+    pub added: Vec<ListedEntry>,
+    /// Entries in the first file but not in the second file
+    pub removed: Vec<ListedEntry>,
+    pub structure_mismatches: Vec<StructureMismatch>,
+    /// Only computed when texts are compared
+    pub text_changes: Option<Vec<TextChangeReport>>,
+}
+
+/// An entry present in only one file
+#[derive(Debug, Serialize)]
+pub struct ListedEntry {
+    pub id: i64,
+    /// Where the entry is in the file where it exists, the last definition for a duplicated id
+    pub position: Option<LinePosition>,
+    /// The (male) text, references to other entries or to dialog.tlk are kept as `@id` or `#strref`
+    pub text: String,
+    pub sound: Option<String>,
+    pub female_variant: bool,
+    /// The comments documenting the entry in the file where it is, without `//` or `/* */`
+    pub comments: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StructureMismatch {
+    pub id: i64,
+    pub differences: Vec<StructureDifference>,
+}
+
+/// Same as `ShapeMismatch`, with the value in the first file then in the second file
+#[derive(Debug, Serialize)]
+#[serde(tag = "property", rename_all = "snake_case")]
+pub enum StructureDifference {
+    Kind { first: Kind, second: Kind },
+    Sound { first: bool, second: bool },
+    FemaleVariant { first: bool, second: bool },
+    FemaleSound { first: bool, second: bool },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Explicit,
+    At,
+    Tlk,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextChangeReport {
+    pub id: i64,
+    /// The change is in the female variant
+    pub female: bool,
+    pub old: Vec<Segment>,
+    pub new: Vec<Segment>,
+}
+
+impl Report {
+    pub fn parse_failed(&self) -> bool {
+        self.first.parse_failed || self.second.parse_failed
+    }
+}
+
+impl SyntaxError {
+    /// An error of the parser, at the position it gives
+    fn of_error(error: &TraError, source: &str, path: &str) -> Self {
+        SyntaxError::new(Diagnostic::from_error(error), Some(error.position.clone()), source, path)
+    }
+
+    /// A problem found in the source by a heuristic
+    fn of_diagnostic(diagnostic: Diagnostic, source: &str, path: &str) -> Self {
+        let position = LinePosition::from_offset(source, diagnostic.span.start);
+        SyntaxError::new(diagnostic, position, source, path)
+    }
+
+    fn new(diagnostic: Diagnostic, position: Option<LinePosition>, source: &str, path: &str) -> Self {
+        SyntaxError {
+            code: diagnostic.code,
+            position,
+            rendered: diagnostic.render(source, path),
+            message: diagnostic.message,
+            hint: diagnostic.hint,
+        }
+    }
+}
+
+impl Comparison {
+    /// A damaged entry is not really missing, it is already reported with the syntax errors
+    fn without_damaged(mut self, first_damaged: &[i64], second_damaged: &[i64]) -> Self {
+        self.added.retain(|entry| !first_damaged.contains(&entry.id));
+        self.removed.retain(|entry| !second_damaged.contains(&entry.id));
+        self
+    }
+}
+
+impl DirectoryReport {
+    pub fn parse_failed(&self) -> bool {
+        self.files.iter().any(Report::parse_failed)
+    }
+}
+
+impl PairReport {
+    pub fn parse_failed(&self) -> bool {
+        match self {
+            PairReport::Files(report) => report.parse_failed(),
+            PairReport::Directories(report) => report.parse_failed(),
+        }
+    }
+}
+
+impl MatrixReport {
+    pub fn parse_failed(&self) -> bool {
+        self.translations.iter().any(|translation| translation.details.parse_failed())
+    }
+}
+
+impl TranslationCounts {
+    pub fn of(report: &PairReport) -> Self {
+        let mut counts = TranslationCounts::default();
+        match report {
+            PairReport::Files(report) => counts.add(report),
+            PairReport::Directories(report) => {
+                report.files.iter().for_each(|report| counts.add(report));
+                counts.missing_files = report.removed_files.len();
+                counts.extra_files = report.added_files.len();
+            }
+        }
+        counts
+    }
+
+    fn add(&mut self, report: &Report) {
+        self.duplicated_entries += report.second.duplicates.len();
+        self.syntax_errors += report.second.errors.len();
+        if let Some(comparison) = &report.comparison {
+            self.missing_entries += comparison.removed.len();
+            self.extra_entries += comparison.added.len();
+            self.structure_mismatches += comparison.structure_mismatches.len();
+        }
+    }
+}
+
+impl Summary {
+    pub fn of(reports: &[Report]) -> Self {
+        let mut summary = Summary { compared_files: reports.len(), ..Default::default() };
+        for report in reports {
+            if report.categories().contains(&Category::Differences) {
+                summary.files_with_differences += 1;
+            }
+            for file in [&report.first, &report.second] {
+                summary.duplicated_entries += file.duplicates.len();
+                summary.syntax_errors += file.errors.len();
+                summary.parse_failures += usize::from(file.parse_failed);
+            }
+            if let Some(comparison) = &report.comparison {
+                summary.added_entries += comparison.added.len();
+                summary.removed_entries += comparison.removed.len();
+                summary.structure_mismatches += comparison.structure_mismatches.len();
+                summary.text_changes += comparison.text_changes.as_ref().map_or(0, Vec::len);
+            }
+        }
+        summary
+    }
+}
+
+impl From<EntryKind> for Kind {
+    fn from(kind: EntryKind) -> Self {
+        match kind {
+            EntryKind::Explicit => Kind::Explicit,
+            EntryKind::At => Kind::At,
+            EntryKind::Tlk => Kind::Tlk,
+        }
+    }
+}
+
+impl From<Kind> for EntryKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Explicit => EntryKind::Explicit,
+            Kind::At => EntryKind::At,
+            Kind::Tlk => EntryKind::Tlk,
+        }
+    }
+}
+
+impl From<&ShapeMismatch> for StructureDifference {
+    fn from(mismatch: &ShapeMismatch) -> Self {
+        match *mismatch {
+            ShapeMismatch::Kind(first, second) =>
+                StructureDifference::Kind { first: first.into(), second: second.into() },
+            ShapeMismatch::Sound(first, second) => StructureDifference::Sound { first, second },
+            ShapeMismatch::FemaleVariant(first, second) => StructureDifference::FemaleVariant { first, second },
+            ShapeMismatch::FemaleSound(first, second) => StructureDifference::FemaleSound { first, second },
+        }
+    }
+}
+
+/// Compares two files, the first one is taken from `cache` when it was already loaded
+pub fn build_report(cache: &mut FileCache,
+                    first_path: &str, first_charset: &'static Encoding,
+                    second_path: &str, second_charset: &'static Encoding,
+                    compare_texts: bool) -> Result<Report> {
+    let (first, first_file) = cache.load(first_path, first_charset)?;
+    let (second, second_file) = load_file(second_path, second_charset)?;
+    let comparison = match (first_file, second_file) {
+        (Some(first_file), Some(second_file)) =>
+            Some(compare(first_file, &second_file, compare_texts)
+                .without_damaged(&first.damaged_entries, &second.damaged_entries)),
+        _ => None,
+    };
+    Ok(Report { first: first.clone(), second, comparison })
+}
+
+/// A file report with the parsed file, `None` if it could not be parsed
+pub type LoadedFile = (FileReport, Option<TraFile>);
+
+/// Files already read and parsed, so that a reference compared with several translations is loaded once
+#[derive(Debug, Default)]
+pub struct FileCache {
+    files: HashMap<String, LoadedFile>,
+}
+
+impl FileCache {
+    pub fn load(&mut self, path: &str, charset: &'static Encoding) -> Result<&LoadedFile> {
+        if !self.files.contains_key(path) {
+            let loaded = load_file(path, charset)?;
+            self.files.insert(path.to_string(), loaded);
+        }
+        Ok(&self.files[path])
+    }
+}
+
+/// Reads and parses a file, the parsed file is `None` if it could not be parsed
+pub fn load_file(path: &str, charset: &'static Encoding) -> Result<LoadedFile> {
+    let bytes = read(path).with_context(|| format!("Could not read {path}"))?;
+    let (content, charset_replacements) = charset.decode_without_bom_handling(&bytes);
+
+    let mut report = FileReport {
+        path: path.to_string(),
+        charset_replacements,
+        parse_failed: false,
+        errors: Vec::new(),
+        unclosed_strings: Vec::new(),
+        damaged_entries: Vec::new(),
+        duplicates: Vec::new(),
+    };
+    report.unclosed_strings = find_unclosed_strings(&content).into_iter()
+        .map(|diagnostic| SyntaxError::of_diagnostic(diagnostic, &content, path))
+        .collect();
+    let file = match TraFile::parse(content.as_ref()) {
+        Ok(file) => file,
+        Err(LoadError::Parse { error, recovered }) => {
+            report.parse_failed = true;
+            report.errors.extend(recovered.iter().map(|error| SyntaxError::of_error(error, &content, path)));
+            report.errors.push(SyntaxError::of_error(&error, &content, path));
+            return Ok((report, None));
+        }
+        Err(error) => return Err(error.into()),
+    };
+    report.errors.extend(file.errors().iter().map(|error| SyntaxError::of_error(error, &content, path)));
+    report.damaged_entries = file.damaged_ids();
+    report.duplicates = find_duplicates(&file);
+
+    Ok((report, Some(file)))
+}
+
+/// Groups the occurrences of the ids defined more than once
+fn find_duplicates(file: &TraFile) -> Vec<Duplicate> {
+    file.duplicates()
+        .map(|(id, occurrences)| Duplicate {
+            id,
+            // comments inside the entries do not change what weidu reads
+            conflicting: occurrences.iter()
+                .tuple_windows()
+                .any(|(first, second)| !first.content.same_content(&second.content)),
+            occurrences: occurrences.iter()
+                .map(|entry| Occurrence {
+                    position: LinePosition::from_offset(file.source(), entry.span.start),
+                    content: entry.content.to_string(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn compare(first: &TraFile, second: &TraFile, compare_texts: bool) -> Comparison {
+    let common_ids = first.ids().filter(|id| second.contains(*id)).collect::<Vec<_>>();
+
+    // the last occurrence of a duplicated id wins (like weidu does)
+    let structure_mismatches = common_ids.iter()
+        .filter_map(|id| {
+            let mismatches = compare_shapes(first.get(*id)?, second.get(*id)?);
+            if mismatches.is_empty() {
+                None
+            } else {
+                Some(StructureMismatch { id: *id, differences: mismatches.iter().map_into().collect() })
+            }
+        })
+        .collect();
+
+    let text_changes = compare_texts.then(|| text_changes(&common_ids, first, second));
+
+    Comparison {
+        added: entries_only_in(second, first),
+        removed: entries_only_in(first, second),
+        structure_mismatches,
+        text_changes,
+    }
+}
+
+/// The entries of `file` missing in `other`, with their comments to explain what they are
+fn entries_only_in(file: &TraFile, other: &TraFile) -> Vec<ListedEntry> {
+    file.ids()
+        .filter(|id| !other.contains(*id))
+        .filter_map(|id| {
+            let entry = file.get(id)?;
+            Some(ListedEntry {
+                id,
+                position: LinePosition::from_offset(file.source(), entry.span.start),
+                text: entry.content.text(),
+                sound: match &entry.content {
+                    TraEntryContent::Explicit(explicit) => explicit.sound.clone(),
+                    _ => None,
+                },
+                female_variant: entry.content.female_text().is_some(),
+                comments: file.comments_of(entry).into_iter()
+                    .flat_map(EntryComments::iter)
+                    .map(|comment| comment.content().trim().to_string())
+                    .filter(|comment| !comment.is_empty())
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+fn text_changes(common_ids: &[i64], first_file: &TraFile, second_file: &TraFile) -> Vec<TextChangeReport> {
+    let mut changes = Vec::new();
+    for id in common_ids {
+        let (Some(first), Some(second)) = (first_file.get(*id), second_file.get(*id)) else {
+            continue;
+        };
+        let (first, second) = (&first.content, &second.content);
+        // entries with a different structure are already reported
+        if first.shape().kind != second.shape().kind {
+            continue;
+        }
+        if let Some(change) = word_diff(&first.text(), &second.text()) {
+            changes.push(TextChangeReport { id: *id, female: false, old: change.old, new: change.new });
+        }
+        if let (Some(first), Some(second)) = (first.female_text(), second.female_text()) {
+            if let Some(change) = word_diff(&first, &second) {
+                changes.push(TextChangeReport { id: *id, female: true, old: change.old, new: change.new });
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+pub mod tests {
+    use tradiff_lib::{parse_trafile, TraFile};
+
+    use crate::report::{entries_only_in, find_duplicates, SyntaxError};
+
+    /// Line and content of an occurrence
+    type Line = (usize, String);
+
+    fn duplicates_of(source: &str) -> Vec<(i64, bool, Vec<Line>)> {
+        find_duplicates(&TraFile::parse(source).unwrap()).into_iter()
+            .map(|duplicate| (
+                duplicate.id,
+                duplicate.conflicting,
+                duplicate.occurrences.into_iter()
+                    .map(|occurrence| (occurrence.position.unwrap().line, occurrence.content))
+                    .collect(),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn identical_and_conflicting_duplicates() {
+        let source = "@1 = ~same~\n@2 = ~first~\n@3 = ~unique~\n@1 = ~same~\n@2 = ~second~ [SND]\n@2 = ~first~\n";
+        assert_eq!(duplicates_of(source), vec![
+            (1, false, vec![(1, "~same~".to_string()), (4, "~same~".to_string())]),
+            (2, true, vec![
+                (2, "~first~".to_string()),
+                (5, "~second~ [SND]".to_string()),
+                (6, "~first~".to_string()),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn syntax_errors_at_the_end_of_the_file_have_a_position() {
+        let source = "@1 = ~a~\n@2 =";
+        let mut errors = Vec::new();
+        parse_trafile(&mut errors, source).unwrap();
+
+        let error = SyntaxError::of_error(&errors[0], source, "x.tra");
+        assert_eq!(error.position.map(|position| (position.line, position.col)), Some((2, 5)));
+    }
+
+    #[test]
+    fn duplicates_differing_only_by_comments_are_identical() {
+        let source = "@1 = /* a */ ~x~\n@1 = ~x~\n";
+        assert_eq!(duplicates_of(source), vec![
+            (1, false, vec![(1, "~x~".to_string()), (2, "~x~".to_string())]),
+        ]);
+    }
+
+    #[test]
+    fn missing_entries_with_their_comments() {
+        let reference = TraFile::parse("// Imoen banter\n@1 = ~Hi~\n@2 = ~Bye~ // Jaheira\n\n//\n@3 = ~x~\n").unwrap();
+        let translation = TraFile::parse("@3 = ~y~\n").unwrap();
+        let missing = entries_only_in(&reference, &translation).into_iter()
+            .map(|entry| (entry.id, entry.comments))
+            .collect::<Vec<_>>();
+        assert_eq!(missing, vec![
+            (1, vec!["Imoen banter".to_string()]),
+            (2, vec!["Jaheira".to_string()]),
+        ]);
+        assert!(entries_only_in(&translation, &reference).is_empty());
+    }
+
+    #[test]
+    fn missing_entries_with_their_content_and_line() {
+        let reference = TraFile::parse("@1 = ~Hi~\n@2 = ~Bye~ [JAH01] ~Bye girl~\n@3 = @1\n@2 = ~Farewell~\n").unwrap();
+        let translation = TraFile::parse("").unwrap();
+        let missing = entries_only_in(&reference, &translation).into_iter()
+            .map(|entry| (entry.id, entry.position.map(|position| position.line), entry.text, entry.sound, entry.female_variant))
+            .collect::<Vec<_>>();
+        // the last definition of a duplicated id is shown
+        assert_eq!(missing, vec![
+            (1, Some(1), "Hi".to_string(), None, false),
+            (2, Some(4), "Farewell".to_string(), None, false),
+            (3, Some(3), "@1".to_string(), None, false),
+        ]);
+
+        let reference = TraFile::parse("@1 = ~Bye~ [JAH01] ~Bye girl~\n").unwrap();
+        let missing = entries_only_in(&reference, &translation);
+        assert_eq!(missing[0].sound.as_deref(), Some("JAH01"));
+        assert!(missing[0].female_variant);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs::{write, File};
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use encoding_rs::Encoding;
+use itertools::Itertools;
+use nu_ansi_term::Color;
+use tradiff_lib::{TraComment, TraDocument, TraEntry, TraFile};
+
+use crate::args::StubArgs;
+use crate::outcome::SUCCESS;
+use crate::report::load_file;
+
+fn todo_comment() -> TraComment {
+    TraComment::EndOfLine(" TODO translate".to_string())
+}
+
+pub fn run_stub(args: &StubArgs) -> Result<u8> {
+    let (reference_charset, translation_charset) = args.charsets.encodings()?;
+    let reference = load_entries(&args.reference, reference_charset)?;
+    let translation = load_entries(&args.translation, translation_charset)?;
+
+    let missing = missing_entries(&reference, &translation);
+    let stub = stub_text(&missing, args.todo);
+
+    if let Some(output) = &args.output {
+        let mut file = File::create_new(output).with_context(|| format!("Could not create {output}"))?;
+        file.write_all(&encode(&stub, translation_charset, output)?)?;
+        eprintln!("{} missing entries written to {output}", missing.len());
+    } else if args.append {
+        if missing.is_empty() {
+            eprintln!("No missing entries, {} is unchanged", args.translation);
+            return Ok(SUCCESS);
+        }
+        let completed = with_entries_inserted(translation.source(), &missing, args.todo)?;
+        write(&args.translation, encode(&completed, translation_charset, &args.translation)?)
+            .with_context(|| format!("Could not write {}", args.translation))?;
+        eprintln!("{} missing entries added to {}", missing.len(), args.translation);
+    } else {
+        print!("{stub}");
+    }
+    Ok(SUCCESS)
+}
+
+fn load_entries(path: &str, charset: &'static Encoding) -> Result<TraFile> {
+    let (report, file) = load_file(path, charset)?;
+    match file {
+        Some(file) => {
+            if !report.errors.is_empty() {
+                eprintln!("🚨 {} {path} contains syntax errors, some entries may be missed", Color::Red.paint("ERROR"));
+            }
+            Ok(file)
+        }
+        None => bail!("{path} could not be parsed"),
+    }
+}
+
+pub fn encode(text: &str, charset: &'static Encoding, path: &str) -> Result<Vec<u8>> {
+    let (bytes, _, replacements) = charset.encode(text);
+    if replacements {
+        bail!("Some characters can not be written to {path} with the {} charset", charset.name());
+    }
+    Ok(bytes.into_owned())
+}
+
+/// The entries of the reference that are missing in the translation, sorted by id.
+/// When an id is duplicated in the reference, the last entry is kept (like weidu does).
+pub fn missing_entries<'a>(reference: &'a TraFile, translation: &TraFile) -> Vec<&'a TraEntry> {
+    reference.ids()
+        .filter(|id| !translation.contains(*id))
+        .filter_map(|id| reference.get(id))
+        .collect()
+}
+
+/// The source with the entries inserted in id order among its entries, the rest of the source is unchanged
+pub fn with_entries_inserted(source: &str, entries: &[&TraEntry], todo: bool) -> Result<String> {
+    let mut document = TraDocument::parse(&mut Vec::new(), source)?;
+    for entry in entries {
+        document.insert_entry(entry.id, entry.content.clone())?;
+        if todo {
+            document.insert_comment_before(entry.id, &todo_comment())?;
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// The entries in TRA syntax, one per line
+pub fn stub_text(entries: &[&TraEntry], todo: bool) -> String {
+    entries.iter()
+        .map(|entry| if todo { format!("{}\n{entry}\n", todo_comment()) } else { format!("{entry}\n") })
+        .join("")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use tradiff_lib::TraFile;
+
+    use crate::stub::{missing_entries, stub_text, with_entries_inserted};
+
+    fn entries(content: &str) -> TraFile {
+        TraFile::parse(content).unwrap()
+    }
+
+    #[test]
+    fn missing_entries_in_id_order() {
+        let reference = entries("@3 = ~c~ @1 = ~a~ [ASOUND] ~fa~ @2 = ~b~ @1 = ~a2~");
+        let translation = entries("@2 = ~bb~");
+
+        let missing = missing_entries(&reference, &translation);
+
+        assert_eq!(stub_text(&missing, false), "@1 = ~a2~\n@3 = ~c~\n");
+    }
+
+    #[test]
+    fn stub_with_todo_comments() {
+        let reference = entries("@1 = ~a~ [ASOUND] \"fa\" [FSOUND] @2 = #1234");
+
+        let missing = missing_entries(&reference, &entries(""));
+
+        assert_eq!(
+            stub_text(&missing, true),
+            "// TODO translate\n@1 = ~a~ [ASOUND] \"fa\" [FSOUND]\n// TODO translate\n@2 = #1234\n"
+        );
+    }
+
+    #[test]
+    fn append_inserts_in_id_order() {
+        let reference = entries("@1 = ~a~ @2 = ~b~ @3 = ~c~ @5 = ~e~");
+        let translation = entries("// about 2\n@2 = ~bb~ // after 2\n@4 = ~dd~");
+
+        let missing = missing_entries(&reference, &translation);
+
+        // the comments of an entry stay with it
+        assert_eq!(
+            with_entries_inserted(translation.source(), &missing, true).unwrap(),
+            "// TODO translate\n@1 = ~a~\n// about 2\n@2 = ~bb~ // after 2\n// TODO translate\n@3 = ~c~\n@4 = ~dd~\n\
+             // TODO translate\n@5 = ~e~"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use itertools::Itertools;
+use nu_ansi_term::Color;
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+/// A part of a text, marked as changed or not compared to the other version of the text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    pub text: String,
+    pub changed: bool,
+}
+
+/// Both versions of a text, split in changed and unchanged segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChange {
+    pub old: Vec<Segment>,
+    pub new: Vec<Segment>,
+}
+
+/// Word-level comparison of two texts, returns `None` when they are identical
+pub fn word_diff(old: &str, new: &str) -> Option<TextChange> {
+    if old == new {
+        return None;
+    }
+    let diff = TextDiff::from_words(old, new);
+    let mut change = TextChange { old: Vec::new(), new: Vec::new() };
+    for item in diff.iter_all_changes() {
+        match item.tag() {
+            ChangeTag::Equal => {
+                push_segment(&mut change.old, item.value(), false);
+                push_segment(&mut change.new, item.value(), false);
+            }
+            ChangeTag::Delete => push_segment(&mut change.old, item.value(), true),
+            ChangeTag::Insert => push_segment(&mut change.new, item.value(), true),
+        }
+    }
+    Some(change)
+}
+
+fn push_segment(segments: &mut Vec<Segment>, text: &str, changed: bool) {
+    match segments.last_mut() {
+        Some(last) if last.changed == changed => last.text.push_str(text),
+        _ => segments.push(Segment { text: text.to_string(), changed }),
+    }
+}
+
+/// Renders the segments with the changed parts highlighted in the given color
+pub fn paint_segments(segments: &[Segment], color: Color) -> String {
+    segments.iter()
+        .map(|segment| if segment.changed {
+            color.bold().underline().paint(&segment.text).to_string()
+        } else {
+            segment.text.clone()
+        })
+        .join("")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::text_diff::{word_diff, Segment};
+
+    fn segment(text: &str, changed: bool) -> Segment {
+        Segment { text: text.to_string(), changed }
+    }
+
+    #[test]
+    fn identical_texts() {
+        assert_eq!(word_diff("Hello there", "Hello there"), None);
+    }
+
+    #[test]
+    fn single_word_changed() {
+        let change = word_diff("Hello there, friend", "Hello there, stranger").unwrap();
+        assert_eq!(change.old, vec![segment("Hello there, ", false), segment("friend", true)]);
+        assert_eq!(change.new, vec![segment("Hello there, ", false), segment("stranger", true)]);
+    }
+
+    #[test]
+    fn word_inserted() {
+        let change = word_diff("Hello friend", "Hello dear friend").unwrap();
+        assert_eq!(change.old, vec![segment("Hello friend", false)]);
+        assert_eq!(change.new, vec![segment("Hello ", false), segment("dear ", true), segment("friend", false)]);
+    }
+}
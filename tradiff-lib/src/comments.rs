@@ -0,0 +1,101 @@
+use crate::tra_structs::{TraComment, TraFragment};
+
+/// The comments documenting an entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntryComments {
+    /// Comments just before the entry, without an empty line between them, in file order
+    pub leading: Vec<TraComment>,
+    /// Comments after the entry, on the same line, in file order
+    pub trailing: Vec<TraComment>,
+}
+
+impl EntryComments {
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_empty()
+    }
+
+    /// All the comments, in file order
+    pub fn iter(&self) -> impl Iterator<Item = &TraComment> {
+        self.leading.iter().chain(&self.trailing)
+    }
+}
+
+/// The entry a comment fragment documents, as an index in the fragments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Attachment {
+    Leading(usize),
+    Trailing(usize),
+}
+
+/// Finds the entry each comment documents, `None` for the comments on their own.
+///
+/// The comments on the same line as an entry and after it are trailing comments of the entry. The comments just
+/// before an entry, without an empty line between them and the entry, are its leading comments.
+/// Comments before input skipped after a syntax error are not attached.
+pub(crate) fn attach_comments<'a>(source: &str, fragments: impl Iterator<Item = &'a TraFragment>)
+                                  -> Vec<Option<Attachment>> {
+    let mut attachments = Vec::new();
+    // comments not attached yet, with the presence of an empty line before them
+    let mut pending = Vec::<(usize, bool)>::new();
+    // the last entry, while only comments on its line follow it
+    let mut last_entry = None;
+    let mut previous_end = None;
+    for (index, fragment) in fragments.enumerate() {
+        attachments.push(None);
+        let span = fragment.span();
+        let blank_before = previous_end.is_some_and(|end| blank_line_between(source, end, span.start));
+        let same_line = previous_end.is_some_and(|end: usize| {
+            source.get(end..span.start).is_some_and(|between| !between.contains('\n')) && !source[..end].ends_with('\n')
+        });
+        previous_end = Some(span.end);
+        match fragment {
+            TraFragment::Comment(..) => match last_entry {
+                Some(entry) if same_line => attachments[index] = Some(Attachment::Trailing(entry)),
+                _ => {
+                    last_entry = None;
+                    pending.push((index, blank_before));
+                }
+            },
+            TraFragment::Entry(_) => {
+                let attached_from = if blank_before {
+                    pending.len()
+                } else {
+                    pending.iter().rposition(|(_, blank)| *blank).unwrap_or(0)
+                };
+                for (comment, _) in pending.drain(..).skip(attached_from) {
+                    attachments[comment] = Some(Attachment::Leading(index));
+                }
+                last_entry = Some(index);
+            }
+            TraFragment::Error { .. } => {
+                pending.clear();
+                last_entry = None;
+            }
+        }
+    }
+    attachments
+}
+
+/// The comments of each entry, indexed like the fragments. Other fragments have no comments.
+pub(crate) fn entry_comments<'a>(source: &str, fragments: impl Iterator<Item = &'a TraFragment> + Clone)
+                                 -> Vec<EntryComments> {
+    let attachments = attach_comments(source, fragments.clone());
+    let mut comments = vec![EntryComments::default(); attachments.len()];
+    for (fragment, attachment) in fragments.zip(attachments) {
+        let TraFragment::Comment(comment, _) = fragment else { continue };
+        match attachment {
+            Some(Attachment::Leading(entry)) => comments[entry].leading.push(comment.clone()),
+            Some(Attachment::Trailing(entry)) => comments[entry].trailing.push(comment.clone()),
+            None => {}
+        }
+    }
+    comments
+}
+
+/// At least two line breaks between the offsets, an end of line comment ends with a line break.
+/// False when the offsets are not in order.
+pub(crate) fn blank_line_between(source: &str, end: usize, start: usize) -> bool {
+    source.get(end..start).is_some_and(|between| {
+        between.matches('\n').count() + usize::from(source[..end].ends_with('\n')) >= 2
+    })
+}
@@ -0,0 +1,139 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use logos::Logos;
+
+use crate::error::TraError;
+use crate::parsers::parse_tokens;
+use crate::token::{LexErrorKind, Token};
+use crate::tra_structs::{Span, TraFragment};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CstTokenKind {
+    Token(Token),
+    /// Spaces, tabs and line breaks
+    Whitespace,
+    /// Input the lexer could not read
+    Invalid(LexErrorKind),
+}
+
+/// A piece of the source, tokens follow each other without gaps
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+    pub kind: CstTokenKind,
+    pub span: Span,
+    /// The source of the token, as written
+    pub text: String,
+}
+
+impl CstToken {
+    fn new(kind: CstTokenKind, source: &str, range: Range<usize>) -> Self {
+        CstToken { kind, span: Span::new(range.start, range.end), text: source[range].to_string() }
+    }
+
+    /// Whitespace, comments and invalid input, which do not change the entries
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind,
+            CstTokenKind::Whitespace | CstTokenKind::Invalid(_)
+            | CstTokenKind::Token(Token::EndOfLineComment(_) | Token::EnclosedComment(_)))
+    }
+}
+
+/// A fragment of the file and the tokens it is made of
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstNode {
+    /// Indexes in the tokens of the tree
+    pub tokens: Range<usize>,
+    pub fragment: TraFragment,
+}
+
+/// Lossless syntax tree of a TRA file: all the source is kept in tokens, including whitespace, comments and
+/// invalid input, so that writing the tokens gives back exactly the source.
+///
+/// The fragments are a view on the tokens: they are parsed from the tokens that are not whitespace or invalid
+/// input, and each one covers a range of tokens. Whitespace between fragments belongs to no fragment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+    source: String,
+    tokens: Vec<CstToken>,
+    nodes: Vec<CstNode>,
+}
+
+impl Cst {
+    /// Parses a TRA file, like `parse_trafile`
+    pub fn parse(errors: &mut Vec<TraError>, source: &str) -> Result<Cst, Box<TraError>> {
+        let tokens = tokenize(source);
+        let parsed_tokens = tokens.iter().filter_map(|token| match &token.kind {
+            CstTokenKind::Token(parsed) => Some((Ok(parsed.clone()), token.span.range())),
+            CstTokenKind::Invalid(kind) => Some((Err(kind.clone()), token.span.range())),
+            CstTokenKind::Whitespace => None,
+        });
+        let fragments = parse_tokens(errors, source, parsed_tokens)?;
+        let nodes = fragments.into_iter()
+            .map(|fragment| CstNode { tokens: token_range(&tokens, fragment.span()), fragment })
+            .collect();
+        Ok(Cst { source: source.to_string(), tokens, nodes })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn tokens(&self) -> &[CstToken] {
+        &self.tokens
+    }
+
+    pub fn nodes(&self) -> &[CstNode] {
+        &self.nodes
+    }
+
+    pub fn fragments(&self) -> impl Iterator<Item = &TraFragment> + Clone {
+        self.nodes.iter().map(|node| &node.fragment)
+    }
+
+    /// The text of a fragment, from its first token to its last one
+    pub fn node_text(&self, node: &CstNode) -> String {
+        self.tokens[node.tokens.clone()].iter().map(|token| token.text.as_str()).collect()
+    }
+}
+
+impl Display for Cst {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.tokens.iter().try_for_each(|token| f.write_str(&token.text))
+    }
+}
+
+/// Splits the source in tokens, the whitespace skipped by the lexer is kept as tokens
+fn tokenize(source: &str) -> Vec<CstToken> {
+    let mut tokens = Vec::<CstToken>::new();
+    let mut end = 0;
+    for (token, span) in Token::lexer(source).spanned() {
+        if span.start > end {
+            tokens.push(CstToken::new(CstTokenKind::Whitespace, source, end..span.start));
+        }
+        end = span.end;
+        let kind = match token {
+            Ok(token) => CstTokenKind::Token(token),
+            Err(kind) => CstTokenKind::Invalid(kind),
+        };
+        // consecutive invalid characters are a single token, like they are a single error
+        match tokens.last_mut() {
+            Some(last) if matches!(kind, CstTokenKind::Invalid(_)) && last.kind == kind && last.span.end == span.start => {
+                last.span.end = span.end;
+                last.text.push_str(&source[span]);
+            }
+            _ => tokens.push(CstToken::new(kind, source, span)),
+        }
+    }
+    if source.len() > end {
+        tokens.push(CstToken::new(CstTokenKind::Whitespace, source, end..source.len()));
+    }
+    tokens
+}
+
+/// The tokens inside `span`
+fn token_range(tokens: &[CstToken], span: Span) -> Range<usize> {
+    let start = tokens.partition_point(|token| token.span.start < span.start);
+    let end = tokens.partition_point(|token| token.span.end <= span.end);
+    start..end.max(start)
+}
@@ -0,0 +1,173 @@
+use std::fmt::{Display, Formatter};
+
+use crate::error::{TraError, TraErrorKind};
+use crate::line_position::{locate, LinePosition};
+use crate::token::{LexErrorKind, Token};
+use crate::tra_structs::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a TRA file, that can be rendered like a compiler message:
+///
+/// ```text
+/// error[E010]: expected a string, found a sound reference ([SND])
+///  --> setup.tra:2:6
+///   |
+/// 2 | @2 = [SND] ~bbb~
+///   |      ^^^^^
+///   = hint: the sound reference comes after the string
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier of the kind of problem, like `E010`
+    pub code: &'static str,
+    pub message: String,
+    /// What is underlined in the source
+    pub span: Span,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, code, message: message.into(), span, hint: None }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, code, message: message.into(), span, hint: None }
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn from_error(error: &TraError) -> Self {
+        let code = match &error.kind {
+            TraErrorKind::Lex(LexErrorKind::InvalidToken) => "E001",
+            TraErrorKind::Lex(LexErrorKind::IntegerOverflow | LexErrorKind::InvalidDigit | LexErrorKind::InvalidInteger(_)) => "E002",
+            TraErrorKind::Lex(LexErrorKind::UnclosedFiveTildes) => "E004",
+            TraErrorKind::UnexpectedToken => "E010",
+            TraErrorKind::UnexpectedEndOfFile => "E011",
+            TraErrorKind::ExtraToken => "E012",
+            TraErrorKind::InvalidToken => "E013",
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: error.message(),
+            span: error.span,
+            hint: error.hint.clone(),
+        }
+    }
+
+    /// Renders the diagnostic with the line of `source` it points to, underlined
+    pub fn render(&self, source: &str, path: &str) -> String {
+        // the span may come from another version of the source
+        let start = self.span.start.min(source.len());
+        let (LinePosition { line: line_number, col: column }, line_start) = locate(source, start);
+        let line = source[line_start..].split('\n').next().unwrap_or("").trim_end_matches('\r');
+        let margin = " ".repeat(line_number.to_string().len());
+
+        // keep the tabs so that the carets are aligned with the line above
+        let padding = line.chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let underlined_end = self.span.end.min(line_start + line.len());
+        let underline_width = source.get(start..underlined_end)
+            .map_or(0, |underlined| underlined.chars().count())
+            .max(1);
+
+        let mut rendered = format!(
+            "{}[{}]: {}\n{margin}--> {path}:{line_number}:{column}\n{margin} |\n{line_number} | {line}\n{margin} | {padding}{}",
+            self.severity, self.code, self.message, "^".repeat(underline_width),
+        );
+        if let Some(hint) = &self.hint {
+            rendered.push_str(&format!("\n{margin} = hint: {hint}"));
+        }
+        rendered
+    }
+}
+
+/// Human description of a token, as found in the source
+pub fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Id(id) => format!("a reference to another entry (@{id})"),
+        Token::EntryStart(id) => format!("the start of an entry (@{id} =)"),
+        Token::Equal => "=".to_string(),
+        Token::OperatorConcat => "^".to_string(),
+        Token::FiveTildesString(_) => "a string delimited by ~~~~~".to_string(),
+        Token::TildeString(_) => "a string delimited by ~".to_string(),
+        Token::DoubleQuoteString(_) => "a string delimited by \"".to_string(),
+        Token::PercentString(_) => "a string delimited by %".to_string(),
+        Token::EndOfLineComment(_) => "a // comment".to_string(),
+        Token::EnclosedComment(_) => "a /* */ comment".to_string(),
+        Token::SoundRef(sound) => format!("a sound reference ([{sound}])"),
+        Token::TlkRef(strref) => format!("a reference to dialog.tlk (#{strref})"),
+    }
+}
+
+/// Human name of a grammar terminal, as listed in the `expected` tokens of parse errors
+pub fn describe_terminal(terminal: &str) -> &str {
+    match terminal.trim_matches('"') {
+        "id" => "a reference to another entry @123",
+        "entry_start" => "the start of an entry @123 =",
+        "equal" => "=",
+        "concat" => "^",
+        "ftildes" => "a string delimited by ~~~~~",
+        "tilde" => "a string delimited by ~",
+        "dquote" => "a string delimited by \"",
+        "percent" => "a string delimited by %",
+        "eol_cmt" => "a // comment",
+        "encl_cmt" => "a /* */ comment",
+        "soundref" => "a sound reference [..]",
+        "tlkref" => "a reference to dialog.tlk #123",
+        other => other,
+    }
+}
+
+/// The expected terminals, the four kinds of strings are grouped as "a string"
+pub(crate) fn describe_expected(expected: &[String]) -> String {
+    let strings = ["\"tilde\"", "\"dquote\"", "\"percent\"", "\"ftildes\""];
+    let all_strings = strings.iter().all(|string| expected.iter().any(|terminal| terminal == string));
+    let mut descriptions = Vec::new();
+    if all_strings {
+        descriptions.push("a string");
+    }
+    descriptions.extend(expected.iter()
+        .filter(|terminal| !all_strings || !strings.contains(&terminal.as_str()))
+        .map(|terminal| describe_terminal(terminal)));
+    match descriptions.as_slice() {
+        [] => "nothing".to_string(),
+        [single] => single.to_string(),
+        [first @ .., last] => format!("{} or {last}", first.join(", ")),
+    }
+}
+
+pub(crate) fn unexpected_token_hint(token: &Token, expected: &[String]) -> Option<&'static str> {
+    let expects = |terminal: &str| expected.iter().any(|expected| expected.trim_matches('"') == terminal);
+    match token {
+        Token::SoundRef(_) if expects("tilde") => Some("the sound reference comes after the string"),
+        Token::FiveTildesString(_) | Token::TildeString(_) | Token::DoubleQuoteString(_) | Token::PercentString(_)
+            if expects("entry_start") =>
+            Some("an entry has at most two strings (male and female), use ^ to concatenate strings"),
+        Token::Id(_) if expects("entry_start") => Some("an entry starts with @123 ="),
+        Token::EntryStart(_) if expects("tilde") => Some("the previous entry has no value"),
+        Token::Equal => Some("an entry starts with @123 =, with nothing between the id and ="),
+        _ => None,
+    }
+}
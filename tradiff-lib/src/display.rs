@@ -0,0 +1,122 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::tra_structs::{ExplicitTraEntry, TraComment, TraEntry, TraEntryContent, TraFragment, WeiduString, WeiduStringLit};
+
+impl Display for WeiduStringLit {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            WeiduStringLit::Tilde(content) => write!(f, "~{content}~"),
+            WeiduStringLit::DoubleQuote(content) => write!(f, "\"{content}\""),
+            WeiduStringLit::Percent(content) => write!(f, "%{content}%"),
+            WeiduStringLit::FiveTildes(content) => write!(f, "~~~~~{content}~~~~~"),
+        }
+    }
+}
+
+impl Display for WeiduString {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            WeiduString::Literal(lit) => write!(f, "{lit}"),
+            WeiduString::At(id) => write!(f, "@{id}"),
+            WeiduString::Ref(strref) => write!(f, "#{strref}"),
+            WeiduString::Concat(left, right) => write!(f, "{left} ^ {right}"),
+        }
+    }
+}
+
+impl Display for ExplicitTraEntry {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.value)?;
+        if let Some(sound) = &self.sound {
+            write!(f, " [{sound}]")?;
+        }
+        if let Some(alt_value) = &self.alt_value {
+            write!(f, " {alt_value}")?;
+        }
+        if let Some(alt_sound) = &self.alt_sound {
+            write!(f, " [{alt_sound}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TraEntryContent {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            TraEntryContent::Explicit(explicit) => write!(f, "{explicit}"),
+            TraEntryContent::At(id) => write!(f, "@{id}"),
+            TraEntryContent::Tlk(strref) => write!(f, "#{strref}"),
+        }
+    }
+}
+
+/// The comments inside the entry are written before the part they precede
+impl Display for TraEntry {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "@{} =", self.id)?;
+        let parts = match &self.content {
+            TraEntryContent::Explicit(explicit) => {
+                let spans = &explicit.spans;
+                let mut parts = vec![(spans.value, explicit.value.to_string())];
+                parts.extend(explicit.sound.iter()
+                    .map(|sound| (spans.sound.unwrap_or_default(), format!("[{sound}]"))));
+                parts.extend(explicit.alt_value.iter()
+                    .map(|alt_value| (spans.alt_value.unwrap_or_default(), alt_value.to_string())));
+                parts.extend(explicit.alt_sound.iter()
+                    .map(|alt_sound| (spans.alt_sound.unwrap_or_default(), format!("[{alt_sound}]"))));
+                parts
+            }
+            other => vec![(self.span, other.to_string())],
+        };
+        let mut comments = self.comments.iter().peekable();
+        for (span, part) in parts {
+            while let Some((comment, _)) = comments.next_if(|(_, comment_span)| comment_span.start < span.end) {
+                write_inner_comment(f, comment)?;
+            }
+            write!(f, " {part}")?;
+        }
+        for (comment, _) in comments {
+            write_inner_comment(f, comment)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_inner_comment(f: &mut Formatter, comment: &TraComment) -> Result {
+    match comment {
+        TraComment::EndOfLine(_) => writeln!(f, " {comment}"),
+        TraComment::Enclosed(_) => write!(f, " {comment}"),
+    }
+}
+
+/// An end of line comment is written without its line break, which must be added after it
+impl Display for TraComment {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            TraComment::EndOfLine(content) => write!(f, "//{content}"),
+            TraComment::Enclosed(content) => write!(f, "/*{content}*/"),
+        }
+    }
+}
+
+/// Errors have no content and are written as nothing
+impl Display for TraFragment {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            TraFragment::Comment(comment, _) => write!(f, "{comment}"),
+            TraFragment::Entry(entry) => write!(f, "{entry}"),
+            TraFragment::Error { .. } => Ok(()),
+        }
+    }
+}
+
+/// Writes the fragments back to TRA syntax, one per line.
+///
+/// Parsing the result gives back the same fragments (except errors, which are dropped),
+/// with the same string delimiters, concatenations, sound references and comments.
+pub fn write_trafile(fragments: &[TraFragment]) -> String {
+    fragments.iter()
+        .filter(|fragment| !matches!(fragment, TraFragment::Error { .. }))
+        .map(|fragment| format!("{fragment}\n"))
+        .collect()
+}
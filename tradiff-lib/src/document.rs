@@ -0,0 +1,384 @@
+use std::fmt::{Display, Formatter};
+
+use crate::comments::{attach_comments, entry_comments, Attachment, EntryComments};
+use crate::cst::{Cst, CstNode, CstTokenKind};
+use crate::error::TraError;
+use crate::token::Token;
+use crate::tra_structs::{ExplicitTraEntry, Span, TraComment, TraEntry, TraEntryContent, WeiduString, WeiduStringLit};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditError {
+    /// No entry has this id
+    UnknownId(i64),
+    /// An entry with this id already exists
+    AlreadyDefined(i64),
+    /// The text contains the closing delimiter of all the kinds of strings
+    UnwritableText(String),
+    /// A sound name that is empty or contains `]`, it can not be written between brackets
+    InvalidSound(String),
+    /// The edited file could not be parsed, because of syntax errors around the edited entry
+    Parse(Box<TraError>),
+    /// The edit would add syntax errors to the file, these are all the errors of the edited file
+    SyntaxErrors(Vec<TraError>),
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            EditError::UnknownId(id) => write!(f, "there is no entry @{id}"),
+            EditError::AlreadyDefined(id) => write!(f, "the entry @{id} already exists"),
+            EditError::UnwritableText(text) => write!(f, "no string delimiters can hold the text {text:?}"),
+            EditError::InvalidSound(sound) => write!(f, "the sound {sound:?} can not be written between brackets"),
+            EditError::Parse(error) => write!(f, "the edited file can not be parsed: {error}"),
+            EditError::SyntaxErrors(errors) => write!(f, "the edit would add syntax errors, the first one is: {}",
+                errors.first().map(ToString::to_string).unwrap_or_default()),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A TRA file that can be edited without rewriting it: only the text of the edited entries changes,
+/// whitespace and comments everywhere else are kept as they are.
+///
+/// Strings written by the edits keep the delimiters of the strings they replace, or of the neighbour entry
+/// for new entries, when these delimiters can hold the text.
+///
+/// An edit that would add syntax errors to the file is rejected, and the document is left unchanged.
+#[derive(Debug, Clone)]
+pub struct TraDocument {
+    cst: Cst,
+    line_break: &'static str,
+    /// Number of syntax errors in the file
+    error_count: usize,
+    /// The comments of each node, only entries have some
+    comments: Vec<EntryComments>,
+}
+
+impl TraDocument {
+    /// Parses a TRA file, like `parse_trafile`
+    pub fn parse(errors: &mut Vec<TraError>, source: &str) -> Result<TraDocument, Box<TraError>> {
+        let previous_errors = errors.len();
+        let cst = Cst::parse(errors, source)?;
+        let line_break = if source.contains("\r\n") { "\r\n" } else { "\n" };
+        let comments = entry_comments(source, cst.fragments());
+        Ok(TraDocument { cst, line_break, error_count: errors.len() - previous_errors, comments })
+    }
+
+    pub fn cst(&self) -> &Cst {
+        &self.cst
+    }
+
+    /// All the entries, in file order
+    pub fn entries(&self) -> impl Iterator<Item = &TraEntry> {
+        self.cst.fragments().filter_map(|fragment| fragment.as_entry())
+    }
+
+    /// The last definition of `id`, the one weidu uses
+    pub fn get(&self, id: i64) -> Option<&TraEntry> {
+        self.entries().filter(|entry| entry.id == id).last()
+    }
+
+    /// The comments documenting the last definition of `id`, like `TraFile::comments`
+    pub fn comments(&self, id: i64) -> Option<&EntryComments> {
+        self.cst.nodes().iter()
+            .zip(&self.comments)
+            .rfind(|(node, _)| node.fragment.as_entry().is_some_and(|entry| entry.id == id))
+            .map(|(_, comments)| comments)
+    }
+
+    /// Replaces the male text of the entry `id`, its sound and female variant are kept.
+    /// An entry made of a reference becomes an entry with this text.
+    pub fn set_text(&mut self, id: i64, text: &str) -> Result<(), EditError> {
+        let entry = self.get(id).ok_or(EditError::UnknownId(id))?;
+        let literal = |style: &WeiduStringLit| style.with_content(text)
+            .map(WeiduString::Literal)
+            .ok_or_else(|| EditError::UnwritableText(text.to_string()));
+        let content = match &entry.content {
+            TraEntryContent::Explicit(explicit) => TraEntryContent::Explicit(ExplicitTraEntry {
+                value: literal(first_literal(&explicit.value).unwrap_or(&WeiduStringLit::Tilde(String::new())))?,
+                ..explicit.clone()
+            }),
+            TraEntryContent::At(_) | TraEntryContent::Tlk(_) => TraEntryContent::Explicit(ExplicitTraEntry {
+                value: literal(&WeiduStringLit::Tilde(String::new()))?,
+                ..Default::default()
+            }),
+        };
+        self.set_entry(id, content)
+    }
+
+    /// Replaces the content of the entry `id` (the last definition when the id is duplicated).
+    ///
+    /// When the entry keeps the same parts (sounds, female variant), only the parts that change are rewritten
+    /// and the comments inside the entry are kept.
+    pub fn set_entry(&mut self, id: i64, mut content: TraEntryContent) -> Result<(), EditError> {
+        let node = self.last_node(id).ok_or(EditError::UnknownId(id))?;
+        let content_span = self.content_span(node);
+        let old = match &entry_of(node).content {
+            TraEntryContent::Explicit(old) => Some(old.clone()),
+            TraEntryContent::At(_) | TraEntryContent::Tlk(_) => None,
+        };
+        restyle(&mut content, old.as_ref())?;
+        let (Some(old), TraEntryContent::Explicit(new)) = (old, &content) else {
+            return self.apply(vec![(content_span, content.to_string())]);
+        };
+        let same_parts = old.sound.is_some() == new.sound.is_some()
+            && old.alt_value.is_some() == new.alt_value.is_some()
+            && old.alt_sound.is_some() == new.alt_sound.is_some();
+        if !same_parts {
+            return self.apply(vec![(content_span, content.to_string())]);
+        }
+        let mut edits = Vec::new();
+        if old.value != new.value {
+            edits.push((old.spans.value, new.value.to_string()));
+        }
+        if let (Some(span), Some(sound)) = (old.spans.sound, &new.sound) {
+            if old.sound.as_ref() != Some(sound) {
+                edits.push((span, format!("[{sound}]")));
+            }
+        }
+        if let (Some(span), Some(alt_value)) = (old.spans.alt_value, &new.alt_value) {
+            if old.alt_value.as_ref() != Some(alt_value) {
+                edits.push((span, alt_value.to_string()));
+            }
+        }
+        if let (Some(span), Some(alt_sound)) = (old.spans.alt_sound, &new.alt_sound) {
+            if old.alt_sound.as_ref() != Some(alt_sound) {
+                edits.push((span, format!("[{alt_sound}]")));
+            }
+        }
+        self.apply(edits)
+    }
+
+    /// Adds an entry after the entry with the greatest smaller id, or before the entry with the smallest
+    /// greater id (and its leading comments), so that a file sorted by id stays sorted
+    pub fn insert_entry(&mut self, id: i64, content: TraEntryContent) -> Result<(), EditError> {
+        if self.get(id).is_some() {
+            return Err(EditError::AlreadyDefined(id));
+        }
+        let before = self.entry_nodes().filter(|node| entry_of(node).id < id).max_by_key(|node| entry_of(node).id);
+        if let Some(before) = before {
+            let edit = self.insertion_after(before, id, content)?;
+            return self.apply(vec![edit]);
+        }
+        let after = self.entry_nodes().filter(|node| entry_of(node).id > id).min_by_key(|node| entry_of(node).id);
+        let content = restyled(content, after.map(|after| &entry_of(after).content))?;
+        let source = self.cst.source();
+        let edit = match after {
+            Some(after) => {
+                let start = self.documented_start(after);
+                let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+                if is_horizontal_space(&source[line_start..start]) {
+                    (Span::new(line_start, line_start), format!("@{id} = {content}{}", self.line_break))
+                } else {
+                    (Span::new(start, start), format!("@{id} = {content} "))
+                }
+            }
+            None if source.is_empty() || source.ends_with('\n') =>
+                (Span::new(source.len(), source.len()), format!("@{id} = {content}{}", self.line_break)),
+            None => (Span::new(source.len(), source.len()), format!("{}@{id} = {content}", self.line_break)),
+        };
+        self.apply(vec![edit])
+    }
+
+    /// Adds an entry after the entry `after` (its last definition), on the next line
+    pub fn insert_entry_after(&mut self, after: i64, id: i64, content: TraEntryContent) -> Result<(), EditError> {
+        if self.get(id).is_some() {
+            return Err(EditError::AlreadyDefined(id));
+        }
+        let node = self.last_node(after).ok_or(EditError::UnknownId(after))?;
+        let edit = self.insertion_after(node, id, content)?;
+        self.apply(vec![edit])
+    }
+
+    /// Adds a comment before the entry `id` (its last definition), on its own line when the entry starts its line
+    pub fn insert_comment_before(&mut self, id: i64, comment: &TraComment) -> Result<(), EditError> {
+        let node = self.last_node(id).ok_or(EditError::UnknownId(id))?;
+        let source = self.cst.source();
+        let start = node.fragment.span().start;
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let edit = if is_horizontal_space(&source[line_start..start]) {
+            (Span::new(line_start, line_start), format!("{comment}{}", self.line_break))
+        } else {
+            // an end of line comment would hide the entry if it were on the same line
+            let separator = match comment {
+                TraComment::EndOfLine(_) => self.line_break,
+                TraComment::Enclosed(_) => " ",
+            };
+            (Span::new(start, start), format!("{comment}{separator}"))
+        };
+        self.apply(vec![edit])
+    }
+
+    /// Removes all the definitions of `id` with their comments (the leading ones and the ones at the end of the line),
+    /// and their lines when nothing else is on them
+    pub fn remove_entry(&mut self, id: i64) -> Result<(), EditError> {
+        let spans = self.entry_nodes()
+            .filter(|node| entry_of(node).id == id)
+            .map(|node| self.removal_span(node))
+            .collect::<Vec<_>>();
+        if spans.is_empty() {
+            return Err(EditError::UnknownId(id));
+        }
+        self.apply(spans.into_iter().map(|span| (span, String::new())).collect())
+    }
+
+    fn entry_nodes(&self) -> impl Iterator<Item = &CstNode> {
+        self.cst.nodes().iter().filter(|node| node.fragment.as_entry().is_some())
+    }
+
+    fn last_node(&self, id: i64) -> Option<&CstNode> {
+        self.entry_nodes().filter(|node| entry_of(node).id == id).last()
+    }
+
+    /// From the first part of the entry, after `=`, to its end
+    fn content_span(&self, node: &CstNode) -> Span {
+        let tokens = &self.cst.tokens()[node.tokens.clone()];
+        let start = tokens.iter()
+            .skip_while(|token| token.kind != CstTokenKind::Token(Token::Equal))
+            .skip(1)
+            .find(|token| !token.is_trivia())
+            .map_or(node.fragment.span().end, |token| token.span.start);
+        Span::new(start, node.fragment.span().end)
+    }
+
+    /// The next line when the entry ends its line (maybe followed by comments), otherwise just after the entry
+    fn insertion_after(&self, node: &CstNode, id: i64, content: TraEntryContent) -> Result<(Span, String), EditError> {
+        let content = restyled(content, Some(&entry_of(node).content))?;
+        let source = self.cst.source();
+        let end = node.fragment.span().end;
+        let edit = match end_of_line_after(source, end) {
+            None => (Span::new(end, end), format!(" @{id} = {content}")),
+            Some(line_end) if line_end < source.len() =>
+                (Span::new(line_end + 1, line_end + 1), format!("@{id} = {content}{}", self.line_break)),
+            Some(_) => (Span::new(source.len(), source.len()), format!("{}@{id} = {content}", self.line_break)),
+        };
+        Ok(edit)
+    }
+
+    /// Where the entry starts, with its leading comments
+    fn documented_start(&self, node: &CstNode) -> usize {
+        let fragments = self.cst.fragments();
+        let index = self.cst.nodes().iter().position(|other| std::ptr::eq(other, node));
+        attach_comments(self.cst.source(), fragments.clone()).into_iter()
+            .zip(fragments)
+            .find(|(attachment, _)| index.is_some_and(|index| *attachment == Some(Attachment::Leading(index))))
+            .map_or(node.fragment.span().start, |(_, comment)| comment.span().start)
+    }
+
+    fn removal_span(&self, node: &CstNode) -> Span {
+        let source = self.cst.source();
+        let (start, end) = (self.documented_start(node), node.fragment.span().end);
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        if let Some(line_end) = end_of_line_after(source, end).filter(|_| is_horizontal_space(&source[line_start..start])) {
+            return Span::new(line_start, (line_end + 1).min(source.len()));
+        }
+        let rest_of_line = source[end..].split('\n').next().unwrap_or_default();
+        let spaces_after = rest_of_line.len() - rest_of_line.trim_start_matches([' ', '\t']).len();
+        if spaces_after < rest_of_line.len() {
+            Span::new(start, end + spaces_after)
+        } else {
+            Span::new(source[..start].trim_end_matches([' ', '\t']).len(), end)
+        }
+    }
+
+    /// Replaces the text of the spans, which do not overlap, and parses the result.
+    /// The document is unchanged when the result has more syntax errors than the file had.
+    fn apply(&mut self, mut edits: Vec<(Span, String)>) -> Result<(), EditError> {
+        let mut source = self.cst.source().to_string();
+        edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+        for (span, text) in edits {
+            source.replace_range(span.range(), &text);
+        }
+        let mut errors = Vec::new();
+        let cst = Cst::parse(&mut errors, &source).map_err(EditError::Parse)?;
+        if errors.len() > self.error_count {
+            return Err(EditError::SyntaxErrors(errors));
+        }
+        self.comments = entry_comments(&source, cst.fragments());
+        self.cst = cst;
+        self.error_count = errors.len();
+        Ok(())
+    }
+}
+
+impl Display for TraDocument {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.cst.fmt(f)
+    }
+}
+
+fn entry_of(node: &CstNode) -> &TraEntry {
+    node.fragment.as_entry().expect("only entry nodes are looked up")
+}
+
+/// The offset of the line break (or of the end of the source) ending the line of `end`,
+/// when only spaces and comments follow `end` on this line
+fn end_of_line_after(source: &str, mut end: usize) -> Option<usize> {
+    loop {
+        let rest = source[end..].trim_start_matches([' ', '\t', '\r']);
+        end = source.len() - rest.len();
+        if rest.is_empty() || rest.starts_with('\n') {
+            return Some(end);
+        } else if rest.starts_with("//") {
+            return Some(rest.find('\n').map_or(source.len(), |newline| end + newline));
+        } else if rest.starts_with("/*") {
+            end += rest.find("*/")? + "*/".len();
+        } else {
+            return None;
+        }
+    }
+}
+
+fn is_horizontal_space(text: &str) -> bool {
+    text.chars().all(|c| c == ' ' || c == '\t' || c == '\r')
+}
+
+fn first_literal(string: &WeiduString) -> Option<&WeiduStringLit> {
+    match string {
+        WeiduString::Literal(lit) => Some(lit),
+        WeiduString::At(_) | WeiduString::Ref(_) => None,
+        WeiduString::Concat(left, right) => first_literal(left).or_else(|| first_literal(right)),
+    }
+}
+
+fn restyled(mut content: TraEntryContent, like: Option<&TraEntryContent>) -> Result<TraEntryContent, EditError> {
+    let like = match like {
+        Some(TraEntryContent::Explicit(like)) => Some(like),
+        _ => None,
+    };
+    restyle(&mut content, like)?;
+    Ok(content)
+}
+
+/// Makes every string of `content` writable, with the delimiters of the strings of `like` when they can hold
+/// the text. Fails when a string or a sound can not be written at all.
+fn restyle(content: &mut TraEntryContent, like: Option<&ExplicitTraEntry>) -> Result<(), EditError> {
+    let TraEntryContent::Explicit(explicit) = content else {
+        return Ok(());
+    };
+    if let Some(sound) = [&explicit.sound, &explicit.alt_sound].into_iter().flatten()
+        .find(|sound| sound.is_empty() || sound.contains(']')) {
+        return Err(EditError::InvalidSound(sound.clone()));
+    }
+    restyle_string(&mut explicit.value, like.map(|like| &like.value))?;
+    if let Some(alt_value) = &mut explicit.alt_value {
+        restyle_string(alt_value, like.map(|like| like.alt_value.as_ref().unwrap_or(&like.value)))?;
+    }
+    Ok(())
+}
+
+fn restyle_string(string: &mut WeiduString, like: Option<&WeiduString>) -> Result<(), EditError> {
+    match string {
+        WeiduString::Literal(lit) => {
+            let style = like.and_then(first_literal).unwrap_or(lit);
+            *lit = style.with_content(lit.content()).ok_or_else(|| EditError::UnwritableText(lit.content().to_string()))?;
+        }
+        WeiduString::Concat(left, right) => {
+            restyle_string(left, like)?;
+            restyle_string(right, like)?;
+        }
+        WeiduString::At(_) | WeiduString::Ref(_) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::fmt::{Display, Formatter};
+
+use lalrpop_util::ParseError;
+
+use crate::diagnostics::{describe_expected, describe_token, unexpected_token_hint};
+use crate::line_position::{locate, LinePosition};
+use crate::token::{LexError, LexErrorKind, Token};
+use crate::tra_structs::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraErrorKind {
+    /// Input that could not be read, it was skipped
+    Lex(LexErrorKind),
+    /// A token that can not come at this place
+    UnexpectedToken,
+    /// The file ends in the middle of an entry
+    UnexpectedEndOfFile,
+    /// A token after the end of the file
+    ExtraToken,
+    InvalidToken,
+}
+
+/// A syntax error in a TRA file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraError {
+    pub kind: TraErrorKind,
+    pub span: Span,
+    /// Where the error starts
+    pub position: LinePosition,
+    /// What could come at this place, like `a string or a sound reference`
+    pub expected: Option<String>,
+    /// What was found instead, like `a sound reference ([SND])`
+    pub found: Option<String>,
+    /// How the error could be fixed
+    pub hint: Option<String>,
+}
+
+impl TraError {
+    pub(crate) fn from_parse_error(error: ParseError<usize, Token, LexError>, source: &str) -> Self {
+        let (kind, span, expected, found, hint) = match error {
+            ParseError::User { error } => {
+                let hint = match error.kind {
+                    LexErrorKind::InvalidToken =>
+                        Some("strings are delimited by ~, \", % or ~~~~~ and comments start with // or /*"),
+                    LexErrorKind::UnclosedFiveTildes => Some("a ~~~~~ string ends at the first ~~~~~ after the opening one"),
+                    _ => None,
+                };
+                (TraErrorKind::Lex(error.kind), error.span, None, None, hint)
+            }
+            ParseError::InvalidToken { location } =>
+                (TraErrorKind::InvalidToken, Span::new(location, location + 1), None, None, None),
+            ParseError::UnrecognizedEof { location, expected } =>
+                (TraErrorKind::UnexpectedEndOfFile, Span::new(location, location), Some(describe_expected(&expected)),
+                 Some("the end of the file".to_string()), Some("the last entry is not complete")),
+            ParseError::UnrecognizedToken { token: (start, token, end), expected } =>
+                (TraErrorKind::UnexpectedToken, Span::new(start, end), Some(describe_expected(&expected)),
+                 Some(describe_token(&token)), unexpected_token_hint(&token, &expected)),
+            ParseError::ExtraToken { token: (start, token, end) } =>
+                (TraErrorKind::ExtraToken, Span::new(start, end), Some("the end of the file".to_string()),
+                 Some(describe_token(&token)), None),
+        };
+        TraError {
+            kind,
+            span,
+            position: locate(source, span.start).0,
+            expected,
+            found,
+            hint: hint.map(str::to_string),
+        }
+    }
+
+    /// The error without its position
+    pub fn message(&self) -> String {
+        match (&self.kind, &self.expected, &self.found) {
+            (TraErrorKind::Lex(LexErrorKind::InvalidToken), _, _) => "unrecognized input".to_string(),
+            (TraErrorKind::Lex(LexErrorKind::UnclosedFiveTildes), _, _) => "string opened with ~~~~~ but not closed".to_string(),
+            (TraErrorKind::Lex(kind), _, _) => format!("invalid number: {kind}"),
+            (TraErrorKind::InvalidToken, _, _) => "invalid token".to_string(),
+            (_, Some(expected), Some(found)) => format!("expected {expected}, found {found}"),
+            (_, _, _) => "syntax error".to_string(),
+        }
+    }
+}
+
+impl Display for TraError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message(), self.position.line, self.position.col)
+    }
+}
+
+impl std::error::Error for TraError {}
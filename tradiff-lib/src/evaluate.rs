@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::diagnostics::Diagnostic;
+use crate::tra_structs::{Span, TraEntry, TraEntryContent, TraFragment, WeiduString};
+
+/// Texts of dialog.tlk, used to resolve `#strref` references
+pub trait TlkLookup {
+    fn lookup(&self, strref: i64) -> Option<String>;
+}
+
+impl TlkLookup for HashMap<i64, String> {
+    fn lookup(&self, strref: i64) -> Option<String> {
+        self.get(&strref).cloned()
+    }
+}
+
+impl TlkLookup for BTreeMap<i64, String> {
+    fn lookup(&self, strref: i64) -> Option<String> {
+        self.get(&strref).cloned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gender {
+    Male,
+    Female,
+}
+
+/// The texts a player would see for an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvaluatedText {
+    pub male: String,
+    /// Same as the male text when there is no female variant
+    pub female: String,
+}
+
+/// Why the text of an entry could not be computed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// The ids of the entries in the cycle, the first one is repeated at the end
+    Cycle(Vec<i64>),
+    /// `@id` refers to an entry defined in none of the files
+    UndefinedEntry(i64),
+    /// `#strref` is not in the dialog.tlk lookup
+    UndefinedTlk(i64),
+}
+
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            EvaluationError::Cycle(ids) => write!(f, "cycle of references {}",
+                                                  ids.iter().map(|id| format!("@{id}")).collect::<Vec<_>>().join(" → ")),
+            EvaluationError::UndefinedEntry(id) => write!(f, "reference to @{id}, which is not defined"),
+            EvaluationError::UndefinedTlk(strref) => write!(f, "reference to #{strref}, which is not in dialog.tlk"),
+        }
+    }
+}
+
+impl EvaluationError {
+    /// The diagnostic for the entry `id`, whose text could not be computed
+    pub fn to_diagnostic(&self, id: i64, span: Span) -> Diagnostic {
+        let code = match self {
+            EvaluationError::Cycle(_) => "E020",
+            EvaluationError::UndefinedEntry(_) => "E021",
+            EvaluationError::UndefinedTlk(_) => "E022",
+        };
+        Diagnostic::error(code, format!("the text of @{id} can not be computed: {self}"), span)
+    }
+}
+
+/// Texts of all the entries of a file, and the diagnostics for the ones that could not be computed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub texts: BTreeMap<i64, EvaluatedText>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Computes the final texts of entries: concatenations are applied and references are replaced with
+/// the text they point to.
+///
+/// `@id` references are looked up in the file, then in the other files in the order they were added.
+/// `#strref` references are looked up in dialog.tlk when a lookup is given, otherwise they are kept as `#strref`.
+/// Like weidu, when an id is defined several times in a file, the last definition is used.
+pub struct Evaluator<'a> {
+    files: Vec<HashMap<i64, &'a TraEntry>>,
+    tlk: Option<&'a dyn TlkLookup>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(fragments: &'a [TraFragment]) -> Self {
+        Evaluator { files: vec![index_entries(fragments)], tlk: None }
+    }
+
+    /// Another file where `@id` references not defined in the previous files are looked up
+    pub fn with_file(mut self, fragments: &'a [TraFragment]) -> Self {
+        self.files.push(index_entries(fragments));
+        self
+    }
+
+    pub fn with_tlk(mut self, tlk: &'a dyn TlkLookup) -> Self {
+        self.tlk = Some(tlk);
+        self
+    }
+
+    /// Texts of the entry `id`, looked up in all the files
+    pub fn evaluate(&self, id: i64) -> Result<EvaluatedText, EvaluationError> {
+        Ok(EvaluatedText {
+            male: self.entry_text(id, Gender::Male, &mut Vec::new())?,
+            female: self.entry_text(id, Gender::Female, &mut Vec::new())?,
+        })
+    }
+
+    /// Texts of all the entries of the first file
+    pub fn evaluate_all(&self) -> Evaluation {
+        let mut evaluation = Evaluation { texts: BTreeMap::new(), diagnostics: Vec::new() };
+        let mut entries = self.files[0].values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.id);
+        for entry in entries {
+            match self.evaluate(entry.id) {
+                Ok(text) => {
+                    evaluation.texts.insert(entry.id, text);
+                }
+                Err(error) => evaluation.diagnostics.push(error.to_diagnostic(entry.id, entry.span)),
+            }
+        }
+        evaluation.diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        evaluation
+    }
+
+    fn entry(&self, id: i64) -> Option<&'a TraEntry> {
+        self.files.iter().find_map(|file| file.get(&id).copied())
+    }
+
+    /// `visiting` holds the ids of the entries being evaluated, to detect cycles
+    fn entry_text(&self, id: i64, gender: Gender, visiting: &mut Vec<i64>) -> Result<String, EvaluationError> {
+        if let Some(position) = visiting.iter().position(|visited| *visited == id) {
+            let mut cycle = visiting[position..].to_vec();
+            cycle.push(id);
+            return Err(EvaluationError::Cycle(cycle));
+        }
+        let entry = self.entry(id).ok_or(EvaluationError::UndefinedEntry(id))?;
+        visiting.push(id);
+        let text = match &entry.content {
+            TraEntryContent::Explicit(explicit) => {
+                let value = match (gender, &explicit.alt_value) {
+                    (Gender::Female, Some(alt_value)) => alt_value,
+                    _ => &explicit.value,
+                };
+                self.string_text(value, gender, visiting)
+            }
+            TraEntryContent::At(other) => self.entry_text(*other, gender, visiting),
+            TraEntryContent::Tlk(strref) => self.tlk_text(*strref),
+        };
+        visiting.pop();
+        text
+    }
+
+    fn string_text(&self, string: &WeiduString, gender: Gender, visiting: &mut Vec<i64>) -> Result<String, EvaluationError> {
+        match string {
+            WeiduString::Literal(lit) => Ok(lit.content().to_string()),
+            WeiduString::At(id) => self.entry_text(*id, gender, visiting),
+            WeiduString::Ref(strref) => self.tlk_text(*strref),
+            WeiduString::Concat(left, right) =>
+                Ok(self.string_text(left, gender, visiting)? + &self.string_text(right, gender, visiting)?),
+        }
+    }
+
+    fn tlk_text(&self, strref: i64) -> Result<String, EvaluationError> {
+        match self.tlk {
+            Some(tlk) => tlk.lookup(strref).ok_or(EvaluationError::UndefinedTlk(strref)),
+            None => Ok(format!("#{strref}")),
+        }
+    }
+}
+
+/// The last definition of an id wins
+fn index_entries(fragments: &[TraFragment]) -> HashMap<i64, &TraEntry> {
+    fragments.iter()
+        .filter_map(TraFragment::as_entry)
+        .map(|entry| (entry.id, entry))
+        .collect()
+}
@@ -0,0 +1,160 @@
+use crate::comments::{attach_comments, blank_line_between, Attachment};
+use crate::error::TraError;
+use crate::parsers::parse_trafile;
+use crate::tra_structs::{TraComment, TraEntry, TraEntryContent, TraFragment, WeiduString, WeiduStringLit};
+
+/// How `format_trafile` writes a file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatOptions {
+    /// Sort the entries by id, their comments move with them
+    pub sort: bool,
+    /// Write all the strings between `~`, or `~~~~~` when they contain `~`
+    pub normalize_delimiters: bool,
+}
+
+/// An entry with its comments, or a comment on its own
+struct Block<'a> {
+    /// There is an empty line before the block in the source
+    blank_before: bool,
+    /// Comments just before the entry, without an empty line between them
+    leading: Vec<&'a TraComment>,
+    entry: Option<&'a TraEntry>,
+    /// Comments after the entry, on the same line
+    trailing: Vec<&'a TraComment>,
+}
+
+/// Writes a TRA file in a canonical way: one entry per line, written as `@id = ~text~ [SOUND] ~female~`,
+/// comments on their own lines except the ones at the end of the line of an entry.
+/// Empty lines between entries are kept, several consecutive empty lines become one.
+///
+/// The file is not formatted when it contains syntax errors, since the skipped parts would be lost.
+pub fn format_trafile(source: &str, options: FormatOptions) -> Result<String, Vec<TraError>> {
+    let mut errors = Vec::new();
+    let fragments = match parse_trafile(&mut errors, source) {
+        Ok(fragments) if errors.is_empty() => fragments,
+        Ok(_) => return Err(errors),
+        Err(error) => {
+            errors.push(*error);
+            return Err(errors);
+        }
+    };
+    let mut blocks = blocks(source, &fragments);
+    if options.sort {
+        blocks = sorted(blocks);
+    }
+
+    let mut formatted = String::new();
+    for (index, block) in blocks.into_iter().enumerate() {
+        if index > 0 && block.blank_before {
+            formatted.push('\n');
+        }
+        for comment in block.leading {
+            formatted.push_str(&format!("{comment}\n"));
+        }
+        if let Some(entry) = block.entry {
+            if options.normalize_delimiters {
+                formatted.push_str(&with_normalized_delimiters(entry).to_string());
+            } else {
+                formatted.push_str(&entry.to_string());
+            }
+            for comment in block.trailing {
+                formatted.push_str(&format!(" {comment}"));
+            }
+            formatted.push('\n');
+        }
+    }
+    if source.contains("\r\n") {
+        formatted = formatted.replace('\n', "\r\n");
+    }
+    Ok(formatted)
+}
+
+fn blocks<'a>(source: &str, fragments: &'a [TraFragment]) -> Vec<Block<'a>> {
+    let mut blocks = Vec::<Block>::new();
+    // leading comments of the next entry, with the presence of an empty line before them
+    let mut leading = Vec::<(bool, &TraComment)>::new();
+    let mut previous_end = None;
+    for (fragment, attachment) in fragments.iter().zip(attach_comments(source, fragments.iter())) {
+        let span = fragment.span();
+        let blank_before = previous_end.is_some_and(|end| blank_line_between(source, end, span.start));
+        previous_end = Some(span.end);
+        match (fragment, attachment) {
+            (TraFragment::Comment(comment, _), Some(Attachment::Trailing(_))) => {
+                if let Some(block) = blocks.last_mut() {
+                    block.trailing.push(comment);
+                }
+            }
+            (TraFragment::Comment(comment, _), Some(Attachment::Leading(_))) => leading.push((blank_before, comment)),
+            (TraFragment::Comment(comment, _), None) => blocks.push(Block::comment(blank_before, comment)),
+            (TraFragment::Entry(entry), _) => {
+                let leading = std::mem::take(&mut leading);
+                blocks.push(Block {
+                    blank_before: leading.first().map_or(blank_before, |(blank, _)| *blank),
+                    leading: leading.into_iter().map(|(_, comment)| comment).collect(),
+                    entry: Some(entry),
+                    trailing: Vec::new(),
+                });
+            }
+            (TraFragment::Error { .. }, _) => {}
+        }
+    }
+    blocks
+}
+
+impl<'a> Block<'a> {
+    fn comment(blank_before: bool, comment: &'a TraComment) -> Self {
+        Block { blank_before, leading: vec![comment], entry: None, trailing: Vec::new() }
+    }
+}
+
+/// The comments before the first entry stay at the top and the ones after the last entry stay at the end,
+/// the other comments on their own move with the entry after them
+fn sorted(blocks: Vec<Block>) -> Vec<Block> {
+    let mut header = Vec::new();
+    let mut units = Vec::<Vec<Block>>::new();
+    let mut current = Vec::new();
+    for block in blocks {
+        let is_entry = block.entry.is_some();
+        if units.is_empty() && !is_entry {
+            header.push(block);
+            continue;
+        }
+        current.push(block);
+        if is_entry {
+            units.push(std::mem::take(&mut current));
+        }
+    }
+    // the empty line after the header stays there
+    let blank_after_header = units.first().is_some_and(|unit| unit[0].blank_before);
+    units.sort_by_key(|unit| unit.last().and_then(|block| block.entry).map(|entry| entry.id));
+    if let Some(first) = units.first_mut() {
+        first[0].blank_before = blank_after_header;
+    }
+    header.into_iter().chain(units.into_iter().flatten()).chain(current).collect()
+}
+
+fn with_normalized_delimiters(entry: &TraEntry) -> TraEntry {
+    let mut entry = entry.clone();
+    if let TraEntryContent::Explicit(explicit) = &mut entry.content {
+        normalize_delimiters(&mut explicit.value);
+        if let Some(alt_value) = &mut explicit.alt_value {
+            normalize_delimiters(alt_value);
+        }
+    }
+    entry
+}
+
+fn normalize_delimiters(string: &mut WeiduString) {
+    match string {
+        WeiduString::Literal(lit) => {
+            if let Some(normalized) = WeiduStringLit::Tilde(String::new()).with_content(lit.content()) {
+                *lit = normalized;
+            }
+        }
+        WeiduString::Concat(left, right) => {
+            normalize_delimiters(left);
+            normalize_delimiters(right);
+        }
+        WeiduString::At(_) | WeiduString::Ref(_) => {}
+    }
+}
@@ -0,0 +1,88 @@
+use std::mem::discriminant;
+
+use logos::Logos;
+
+use crate::diagnostics::Diagnostic;
+use crate::line_position::locate;
+use crate::token::Token;
+use crate::tra_structs::Span;
+
+/// Strings with more lines than this are reported as probably not closed
+const SUSPICIOUS_LINE_COUNT: usize = 50;
+
+/// Looks for strings that are probably missing their closing delimiter.
+///
+/// A string that is not closed goes on up to the next delimiter of the same kind, usually the opening
+/// delimiter of the next entry, and the syntax errors are then reported far away from the mistake.
+/// Strings containing a line that starts like an entry (`@123 =`), or with an unusual number of lines,
+/// are reported at their opening delimiter.
+pub fn find_unclosed_strings(source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // after a string that is not closed, the next string of the same kind is usually made of
+    // the closing delimiter of the next entry and what follows, it is not reported again
+    let mut skip_next = None;
+    for (token, span) in Token::lexer(source).spanned() {
+        let Ok(token) = token else {
+            continue;
+        };
+        let delimiter_len = match token {
+            Token::FiveTildesString(_) => 5,
+            Token::TildeString(_) | Token::DoubleQuoteString(_) | Token::PercentString(_) => 1,
+            _ => continue,
+        };
+        let kind = discriminant(&token);
+        if skip_next.take() == Some(kind) {
+            continue;
+        }
+        if let Some(diagnostic) = check_string(source, Span::new(span.start, span.end), delimiter_len) {
+            diagnostics.push(diagnostic);
+            skip_next = Some(kind);
+        }
+    }
+    diagnostics
+}
+
+fn check_string(source: &str, span: Span, delimiter_len: usize) -> Option<Diagnostic> {
+    let content = &source[span.start + delimiter_len..span.end - delimiter_len];
+    let opening = Span::new(span.start, span.start + delimiter_len);
+    let opening_line = locate(source, span.start).0.line;
+    let message = format!("the string opened at line {opening_line} is probably not closed");
+
+    if let Some(offset) = find_entry_start(content) {
+        let entry_line = locate(source, span.start + delimiter_len + offset).0.line;
+        let entry = content[offset..].split('=').next().unwrap_or_default().trim();
+        return Some(Diagnostic::warning("W001", message, opening).with_hint(format!(
+            "it goes on up to line {} and contains `{entry} =` at line {entry_line}, \
+             which looks like the start of an entry",
+            locate(source, span.end).0.line,
+        )));
+    }
+    let line_count = content.lines().count();
+    if line_count > SUSPICIOUS_LINE_COUNT {
+        return Some(Diagnostic::warning("W002", message, opening)
+            .with_hint(format!("it is {line_count} lines long")));
+    }
+    None
+}
+
+/// Offset of the first line (except the first one) starting with `@123 =`, ignoring indentation
+fn find_entry_start(content: &str) -> Option<usize> {
+    let mut line_start = 0;
+    for (index, line) in content.split('\n').enumerate() {
+        let indentation = line.len() - line.trim_start().len();
+        if index > 0 && looks_like_entry_start(&line[indentation..]) {
+            return Some(line_start + indentation);
+        }
+        line_start += line.len() + 1;
+    }
+    None
+}
+
+fn looks_like_entry_start(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('@') else {
+        return false;
+    };
+    let rest = rest.strip_prefix('-').unwrap_or(rest);
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    digits > 0 && rest[digits..].trim_start().starts_with('=')
+}
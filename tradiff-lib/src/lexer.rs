@@ -1,27 +1,111 @@
-use logos::{Logos, SpannedIter};
+use std::collections::VecDeque;
+use std::ops::Range;
 
-use crate::token::{Token, LexError};
+use crate::token::{LexError, LexErrorKind, Token};
+use crate::tra_structs::{Span, TraComment};
 
 pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
 
-pub struct Lexer<'input> {
+pub struct Lexer<'err, I> {
     // instead of an iterator over characters, we have a token iterator
-    token_stream: SpannedIter<'input, Token>,
+    token_stream: I,
+    // lexical errors do not stop the parser, they are collected here
+    errors: &'err mut Vec<LexError>,
+    // comments inside an entry are not given to the parser, they are collected here
+    inner_comments: &'err mut Vec<(TraComment, Span)>,
+    // tokens read ahead, to find out what follows an `Id` or comments
+    buffer: VecDeque<(usize, Token, usize)>,
 }
 
-impl<'input> Lexer<'input> {
-    pub fn new(input: &'input str) -> Self {
-        // the Token::lexer() method is provided by the Logos trait
-        Self { token_stream: Token::lexer(input).spanned() }
+impl<'err, I: Iterator<Item = (Result<Token, LexErrorKind>, Range<usize>)>> Lexer<'err, I> {
+    /// `token_stream` gives the tokens with their place in the input, like the `spanned()` iterator of logos
+    pub fn new(token_stream: I, errors: &'err mut Vec<LexError>,
+               inner_comments: &'err mut Vec<(TraComment, Span)>) -> Self {
+        Self { token_stream, errors, inner_comments, buffer: VecDeque::new() }
+    }
+
+    /// Consecutive invalid characters are reported as a single error
+    fn record(&mut self, error: LexError) {
+        match self.errors.last_mut() {
+            Some(last) if last.kind == error.kind && last.span.end == error.span.start =>
+                last.span.end = error.span.end,
+            _ => self.errors.push(error),
+        }
+    }
+
+    fn next_valid_token(&mut self) -> Option<(usize, Token, usize)> {
+        loop {
+            let (token, span) = self.token_stream.next()?;
+            match token {
+                Ok(token) => return Some((span.start, token, span.end)),
+                // skip the invalid input and go on with the next token
+                Err(kind) => self.record(LexError { kind, span: Span::new(span.start, span.end) }),
+            }
+        }
+    }
+
+    fn peek(&mut self, index: usize) -> Option<&Token> {
+        while self.buffer.len() <= index {
+            let token = self.next_valid_token()?;
+            self.buffer.push_back(token);
+        }
+        self.buffer.get(index).map(|(_, token, _)| token)
+    }
+
+    /// Index in the buffer of the first token that is not a comment, starting at `index`
+    fn skip_comments(&mut self, mut index: usize) -> usize {
+        while matches!(self.peek(index), Some(Token::EndOfLineComment(_) | Token::EnclosedComment(_))) {
+            index += 1;
+        }
+        index
+    }
+
+    /// An `Id` followed by `Equal`, possibly with comments between them
+    fn is_entry_start(&mut self, index: usize) -> bool {
+        if !matches!(self.peek(index), Some(Token::Id(_))) {
+            return false;
+        }
+        let after_id = self.skip_comments(index + 1);
+        matches!(self.peek(after_id), Some(Token::Equal))
+    }
+
+    /// Moves the `count` first tokens of the buffer, which are comments, to the inner comments
+    fn take_inner_comments(&mut self, count: usize) {
+        for (start, token, end) in self.buffer.drain(..count) {
+            let comment = match token {
+                Token::EndOfLineComment(content) => TraComment::EndOfLine(content),
+                Token::EnclosedComment(content) => TraComment::Enclosed(content),
+                _ => unreachable!("only comments are skipped"),
+            };
+            self.inner_comments.push((comment, Span::new(start, end)));
+        }
     }
 }
 
-impl<'input> Iterator for Lexer<'input> {
+impl<I: Iterator<Item = (Result<Token, LexErrorKind>, Range<usize>)>> Iterator for Lexer<'_, I> {
     type Item = Spanned<Token, usize, LexError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.token_stream
-            .next()
-            .map(|(token, span)| Ok((span.start, token?, span.end)))
+        // comments followed by the next part of an entry are inside this entry,
+        // comments followed by the start of an entry or the end of the file are between entries
+        let after_comments = self.skip_comments(0);
+        if after_comments > 0 {
+            if self.peek(after_comments).is_none() || self.is_entry_start(after_comments) {
+                return self.buffer.pop_front().map(Ok);
+            }
+            self.take_inner_comments(after_comments);
+        }
+
+        let (start, token, end) = self.buffer.pop_front()?;
+        let Token::Id(id) = token else {
+            return Some(Ok((start, token, end)));
+        };
+        let after_id = self.skip_comments(0);
+        if !matches!(self.peek(after_id), Some(Token::Equal)) {
+            return Some(Ok((start, token, end)));
+        }
+        self.take_inner_comments(after_id);
+        let (_, _, equal_end) = self.buffer.pop_front()?;
+        Some(Ok((start, Token::EntryStart(id), equal_end)))
     }
 }
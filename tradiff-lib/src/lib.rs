@@ -1,17 +1,39 @@
 
+mod comments;
+mod cst;
+mod diagnostics;
+mod display;
+mod document;
+mod error;
+mod evaluate;
+mod formatter;
+mod heuristics;
 mod lexer;
+mod line_position;
 mod parsers;
+mod shape;
 mod token;
+mod tra_file;
 mod tra_structs;
 #[cfg(test)]
 mod test;
 
 pub use tra_structs::*;
+pub use comments::EntryComments;
+pub use cst::{Cst, CstNode, CstToken, CstTokenKind};
+pub use diagnostics::{describe_terminal, describe_token, Diagnostic, Severity};
+pub use display::write_trafile;
+pub use document::{EditError, TraDocument};
+pub use error::{TraError, TraErrorKind};
+pub use evaluate::{EvaluatedText, Evaluation, EvaluationError, Evaluator, TlkLookup};
+pub use formatter::{format_trafile, FormatOptions};
+pub use heuristics::find_unclosed_strings;
+pub use line_position::LinePosition;
 pub use parsers::parse_trafile;
-pub use token::{Token, LexError};
+pub use shape::{compare_shapes, EntryKind, EntryShape, ShapeMismatch};
+pub use token::{Token, LexError, LexErrorKind};
+pub use tra_file::{LoadError, TraFile};
 
 use lalrpop_util::lalrpop_mod;
 
-lalrpop_util::lalrpop_mod!(tra);
-
-pub use tra::*;
+lalrpop_mod!(#[allow(clippy::all)] pub(crate) tra);
@@ -1,29 +1,17 @@
-
+/// Line and column of an offset in a text
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct LinePosition {
     /// Line number (counting from 1)
     pub line: usize,
-    /// Row number (counting from 1)
+    /// Column number in characters (counting from 1)
     pub col: usize,
 }
 
 impl LinePosition {
+    /// `None` when the offset is at or past the end of the text
     pub fn from_offset(text: &str, offset: usize) -> Option<Self> {
-        if offset + 1 > text.len() {
-            return None;
-        }
-        let mut line_start = 0;
-        let mut line_num = 1usize;
-        for line in text.split('\n') {
-            let line_end = line_start + line.len() + "\n".len();
-            if line_end > offset {
-                // done searching
-                return Some(Self { line: line_num, col: offset - line_start + 1 })
-            }
-            line_num += 1;
-            line_start = line_end;
-        }
-        None
+        (offset < text.len()).then(|| locate(text, offset).0)
     }
 
     #[cfg(test)]
@@ -32,6 +20,16 @@ impl LinePosition {
     }
 }
 
+/// The position of `offset` and the offset of the start of its line.
+/// An offset past the end of the text is moved to the end, the column of an offset inside a character is 1.
+pub(crate) fn locate(text: &str, offset: usize) -> (LinePosition, usize) {
+    let before = &text.as_bytes()[..offset.min(text.len())];
+    let line = before.iter().filter(|byte| **byte == b'\n').count() + 1;
+    let line_start = before.iter().rposition(|byte| *byte == b'\n').map_or(0, |newline| newline + 1);
+    let col = text.get(line_start..before.len()).map_or(1, |start_of_line| start_of_line.chars().count() + 1);
+    (LinePosition { line, col }, line_start)
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::line_position::LinePosition;
@@ -109,4 +107,12 @@ pub mod tests {
         assert_eq!(LinePosition::from_offset(text, 2), Some(LinePosition::new(1, 3))); // \n
         assert_eq!(LinePosition::from_offset(text, 3), None);
     }
+
+    #[test]
+    fn columns_count_characters() {
+        let text = "é~\n\tà ~";
+        assert_eq!(LinePosition::from_offset(text, 2), Some(LinePosition::new(1, 2))); // ~
+        assert_eq!(LinePosition::from_offset(text, 8), Some(LinePosition::new(2, 4))); // ~
+        assert_eq!(LinePosition::from_offset(text, 1), Some(LinePosition::new(1, 1))); // inside é
+    }
 }
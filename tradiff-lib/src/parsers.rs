@@ -1,18 +1,75 @@
+use std::ops::Range;
+
 use lalrpop_util::{ErrorRecovery, ParseError};
+use logos::Logos;
 
+use crate::error::TraError;
 use crate::lexer::Lexer;
-use crate::token::{LexError, Token};
-use crate::{TraFileParser, TraFragment};
+use crate::token::{LexError, LexErrorKind, Token};
+use crate::tra::TraFileParser;
+use crate::tra_structs::{Span, TraComment, TraFragment};
+
 
+/// Parses a TRA file.
+///
+/// The recovered errors, including the input skipped by the lexer, are added to `errors` in file order,
+/// even when the parsing fails.
+pub fn parse_trafile(errors: &mut Vec<TraError>, content: &str) -> Result<Vec<TraFragment>, Box<TraError>> {
+    parse_tokens(errors, content, Token::lexer(content).spanned())
+}
 
-pub fn parse_trafile<'a>(
-    errors: &mut Vec<ErrorRecovery<usize, Token, LexError>>,
-    content: &'a str,
-) -> Result<Vec<TraFragment>, ParseError<usize, Token, LexError>> {
+/// Parses the tokens of `content`, already split by the lexer
+pub(crate) fn parse_tokens(errors: &mut Vec<TraError>, content: &str,
+                           tokens: impl Iterator<Item = (Result<Token, LexErrorKind>, Range<usize>)>)
+                           -> Result<Vec<TraFragment>, Box<TraError>> {
     let mut gather_errors = Vec::new();
-    let normalized = content.replace("\r\n", "\n");
-    let lexer = Lexer::new(&normalized);
-    let result = TraFileParser::new().parse(&mut gather_errors, lexer);
-    errors.extend(gather_errors);
+    let mut lex_errors = Vec::new();
+    let mut inner_comments = Vec::new();
+    let lexer = Lexer::new(tokens, &mut lex_errors, &mut inner_comments);
+    let result = TraFileParser::new().parse(&mut gather_errors, lexer)
+        .map(|fragments| attach_inner_comments(fragments, inner_comments));
+    gather_errors.extend(lex_errors.into_iter().map(|error| ErrorRecovery {
+        error: ParseError::User { error },
+        dropped_tokens: Vec::new(),
+    }));
+    gather_errors.sort_by_key(|recovery| error_location(&recovery.error));
+    errors.extend(gather_errors.into_iter().map(|recovery| TraError::from_parse_error(recovery.error, content)));
+    result.map_err(|error| Box::new(TraError::from_parse_error(error, content)))
+}
+
+/// Adds the comments found inside entries to these entries.
+/// Comments in the input skipped after a syntax error are part of this skipped input, the other comments
+/// that are in no entry are kept as fragments, so that the fragments stay in file order.
+fn attach_inner_comments(fragments: Vec<TraFragment>, comments: Vec<(TraComment, Span)>) -> Vec<TraFragment> {
+    let mut comments = comments.into_iter().peekable();
+    let mut result = Vec::with_capacity(fragments.len());
+    for mut fragment in fragments {
+        let span = fragment.span();
+        while let Some((comment, comment_span)) = comments.next_if(|(_, comment_span)| comment_span.start < span.start) {
+            result.push(TraFragment::Comment(comment, comment_span));
+        }
+        match &mut fragment {
+            TraFragment::Entry(entry) => {
+                while let Some(comment) = comments.next_if(|(_, comment_span)| comment_span.start < span.end) {
+                    entry.comments.push(comment);
+                }
+            }
+            TraFragment::Error { .. } => {
+                while comments.next_if(|(_, comment_span)| comment_span.start < span.end).is_some() {}
+            }
+            TraFragment::Comment(..) => {}
+        }
+        result.push(fragment);
+    }
+    result.extend(comments.map(|(comment, span)| TraFragment::Comment(comment, span)));
     result
 }
+
+/// Byte offset where the error starts
+fn error_location(error: &ParseError<usize, Token, LexError>) -> usize {
+    match error {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => *location,
+        ParseError::UnrecognizedToken { token, .. } | ParseError::ExtraToken { token } => token.0,
+        ParseError::User { error } => error.span.start,
+    }
+}
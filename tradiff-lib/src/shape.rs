@@ -0,0 +1,92 @@
+use crate::tra_structs::{TraEntry, TraEntryContent};
+
+/// What kind of value is on the right side of the `=` of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// `@1 = ~text~` (possibly with sound refs and female variant)
+    Explicit,
+    /// `@1 = @2`
+    At,
+    /// `@1 = #1234`
+    Tlk,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EntryKind::Explicit => write!(f, "explicit text"),
+            EntryKind::At => write!(f, "reference to another entry (@)"),
+            EntryKind::Tlk => write!(f, "reference to dialog.tlk (#)"),
+        }
+    }
+}
+
+/// The structure of an entry, ignoring the actual text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryShape {
+    pub kind: EntryKind,
+    pub has_sound: bool,
+    pub has_female: bool,
+    pub has_female_sound: bool,
+}
+
+/// A structural difference between two entries with the same id.
+/// Each variant holds the value for the first entry then for the second one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShapeMismatch {
+    Kind(EntryKind, EntryKind),
+    Sound(bool, bool),
+    FemaleVariant(bool, bool),
+    FemaleSound(bool, bool),
+}
+
+impl TraEntryContent {
+    pub fn shape(&self) -> EntryShape {
+        match self {
+            TraEntryContent::Explicit(explicit) => EntryShape {
+                kind: EntryKind::Explicit,
+                has_sound: explicit.sound.is_some(),
+                has_female: explicit.alt_value.is_some(),
+                has_female_sound: explicit.alt_sound.is_some(),
+            },
+            TraEntryContent::At(_) => EntryShape::bare(EntryKind::At),
+            TraEntryContent::Tlk(_) => EntryShape::bare(EntryKind::Tlk),
+        }
+    }
+}
+
+impl TraEntry {
+    pub fn shape(&self) -> EntryShape {
+        self.content.shape()
+    }
+}
+
+impl EntryShape {
+    fn bare(kind: EntryKind) -> Self {
+        EntryShape { kind, has_sound: false, has_female: false, has_female_sound: false }
+    }
+
+    /// Lists the differences between two shapes.
+    /// When the kinds differ, the other properties are meaningless and only the kind is reported.
+    pub fn mismatches(&self, other: &EntryShape) -> Vec<ShapeMismatch> {
+        if self.kind != other.kind {
+            return vec![ShapeMismatch::Kind(self.kind, other.kind)];
+        }
+        let mut result = Vec::new();
+        if self.has_sound != other.has_sound {
+            result.push(ShapeMismatch::Sound(self.has_sound, other.has_sound));
+        }
+        if self.has_female != other.has_female {
+            result.push(ShapeMismatch::FemaleVariant(self.has_female, other.has_female));
+        }
+        if self.has_female_sound != other.has_female_sound {
+            result.push(ShapeMismatch::FemaleSound(self.has_female_sound, other.has_female_sound));
+        }
+        result
+    }
+}
+
+/// Structural comparison of two entries (usually with the same id, from two different files)
+pub fn compare_shapes(first: &TraEntry, second: &TraEntry) -> Vec<ShapeMismatch> {
+    first.shape().mismatches(&second.shape())
+}
@@ -1,10 +1,34 @@
 
 use pretty_assertions::assert_eq;
 
+use crate::display::write_trafile;
+use crate::line_position::LinePosition;
 use crate::parsers::parse_trafile;
-use crate::{dquote, ftildes, percent, tilde, ExplicitTraEntry, TraComment, TraEntry, TraFragment, WeiduStringLit};
+use crate::{
+    compare_shapes, dquote, find_unclosed_strings, format_trafile, ftildes, percent, tilde, Cst, CstTokenKind,
+    Diagnostic, EditError, EntryComments, EntryKind, EvaluatedText, EvaluationError, Evaluator, ExplicitSpans,
+    ExplicitTraEntry, FormatOptions, LexErrorKind, LoadError, ShapeMismatch, Span, Token, TraComment, TraDocument,
+    TraEntry, TraEntryContent, TraError, TraErrorKind, TraFile, TraFragment, WeiduString, WeiduStringLit,
+};
 use crate::TraEntryContent::Explicit;
 
+/// Resets the locations, to compare the fragments with expected values built without them
+fn without_spans(mut fragments: Vec<TraFragment>) -> Vec<TraFragment> {
+    for fragment in &mut fragments {
+        match fragment {
+            TraFragment::Comment(_, span) | TraFragment::Error { span, .. } => *span = Span::default(),
+            TraFragment::Entry(entry) => {
+                entry.span = Span::default();
+                entry.comments.iter_mut().for_each(|(_, span)| *span = Span::default());
+                if let Explicit(explicit) = &mut entry.content {
+                    explicit.spans = ExplicitSpans::default();
+                }
+            }
+        }
+    }
+    fragments
+}
+
 #[test]
 fn multiple_string_variants() {
     let input = r#"
@@ -17,12 +41,12 @@ fn multiple_string_variants() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
-            TraFragment::Entry(TraEntry { id: 3, content: Explicit(ExplicitTraEntry::simplest(percent!("ccc")))}),
-            TraFragment::Entry(TraEntry { id: 4, content: Explicit(ExplicitTraEntry::simplest(ftildes!("abc~~abc")))}),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+            TraFragment::Entry(TraEntry::new(3, Explicit(ExplicitTraEntry::simplest(percent!("ccc"))))),
+            TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry::simplest(ftildes!("abc~~abc"))))),
         ])
     )
 }
@@ -36,12 +60,12 @@ fn multiple_entries_on_single_line() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
-            TraFragment::Entry(TraEntry { id: 3, content: Explicit(ExplicitTraEntry::simplest(percent!("ccc")))}),
-            TraFragment::Entry(TraEntry { id: 4, content: Explicit(ExplicitTraEntry::simplest(ftildes!("abc~~abc")))}),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+            TraFragment::Entry(TraEntry::new(3, Explicit(ExplicitTraEntry::simplest(percent!("ccc"))))),
+            TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry::simplest(ftildes!("abc~~abc"))))),
         ])
     )
 }
@@ -59,13 +83,13 @@ fn with_enclosed_comments_between_entries() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Comment(TraComment::Enclosed(" comment 1 ".to_string())),
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Comment(TraComment::Enclosed(" comment 2 ".to_string())),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
-            TraFragment::Comment(TraComment::Enclosed(" comment 3 ".to_string())),
+            TraFragment::Comment(TraComment::Enclosed(" comment 1 ".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Comment(TraComment::Enclosed(" comment 2 ".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+            TraFragment::Comment(TraComment::Enclosed(" comment 3 ".to_string()), Span::default()),
         ])
     )
 }
@@ -82,14 +106,14 @@ fn with_end_of_line_comments() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string())),
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string())),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string())),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 4".to_string())),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string()), Span::default()),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 4".to_string()), Span::default()),
         ])
     )
 }
@@ -102,13 +126,13 @@ fn with_end_of_line_comments_crlf() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, &input).map(without_spans),
         Ok(vec![
-            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string())),
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string())),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string())),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string()), Span::default()),
         ])
     )
 }
@@ -126,11 +150,11 @@ fn with_multiline_enclosed_comment() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))}),
-            TraFragment::Comment(TraComment::Enclosed("\n    comment 2\n    ".to_string())),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(dquote!("bbb")))}),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Comment(TraComment::Enclosed("\n    comment 2\n    ".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
         ])
     )
 }
@@ -146,7 +170,16 @@ fn with_en_of_line_comment_and_junk_on_the_next_line() {
 
     let mut errors = Vec::new();
 
-    assert!(parse_trafile(&mut errors, &input).is_err());
+    // the junk is skipped and reported, the entries around it are still parsed
+    assert_eq!(
+        parse_trafile(&mut errors, input).map(without_spans),
+        Ok(vec![
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa"))))),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(dquote!("bbb"))))),
+        ])
+    );
+    assert_eq!(lex_errors(&errors, input), vec![(LexErrorKind::InvalidToken, "junk")]);
 }
 
 
@@ -162,12 +195,12 @@ fn with_female_variant() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::with_female(tilde!("aaa"), tilde!("aab")))}),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::with_female(dquote!("bbb"), dquote!("bbc")))}),
-            TraFragment::Entry(TraEntry { id: 3, content: Explicit(ExplicitTraEntry::with_female(percent!("ccc"), percent!("ccd")))}),
-            TraFragment::Entry(TraEntry { id: 4, content: Explicit(ExplicitTraEntry::with_female(ftildes!("abc~~abc"), ftildes!("bca~~bca")))}),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::with_female(tilde!("aaa"), tilde!("aab"))))),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::with_female(dquote!("bbb"), dquote!("bbc"))))),
+            TraFragment::Entry(TraEntry::new(3, Explicit(ExplicitTraEntry::with_female(percent!("ccc"), percent!("ccd"))))),
+            TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry::with_female(ftildes!("abc~~abc"), ftildes!("bca~~bca"))))),
         ])
     )
 }
@@ -184,12 +217,12 @@ fn with_male_sound() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::with_sound(tilde!("aaa"), "ASOUND"))}),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::with_sound(dquote!("bbb"), "BSOUND"))}),
-            TraFragment::Entry(TraEntry { id: 3, content: Explicit(ExplicitTraEntry::with_sound(percent!("ccc"), "CSOUND"))}),
-            TraFragment::Entry(TraEntry { id: 4, content: Explicit(ExplicitTraEntry::with_sound(ftildes!("abc~~abc"), "DSOUND"))}),
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::with_sound(tilde!("aaa"), "ASOUND")))),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::with_sound(dquote!("bbb"), "BSOUND")))),
+            TraFragment::Entry(TraEntry::new(3, Explicit(ExplicitTraEntry::with_sound(percent!("ccc"), "CSOUND")))),
+            TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry::with_sound(ftildes!("abc~~abc"), "DSOUND")))),
         ])
     )
 }
@@ -206,24 +239,24 @@ fn with_male_sound_and_female_variant() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry {
-                id: 1,
-                content: Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"), Some(tilde!("aab")), None))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 2,
-                content: Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), None))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 3,
-                content: Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), None))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 4,
-                content: Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), None))
-            }),
+            TraFragment::Entry(TraEntry::new(
+                1,
+                Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"), Some(tilde!("aab")), None))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                2,
+                Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), None))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                3,
+                Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), None))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                4,
+                Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), None))
+            )),
         ])
     )
 }
@@ -240,24 +273,24 @@ fn with_all_elements_in_tra_entry() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry {
-                id: 1,
-                content: Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"), Some(tilde!("aab")), Some("FASOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 2,
-                content: Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), Some("FBSOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 3,
-                content: Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), Some("FCSOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 4,
-                content: Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), Some("FDSOUND")))
-            }),
+            TraFragment::Entry(TraEntry::new(
+                1,
+                Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"), Some(tilde!("aab")), Some("FASOUND")))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                2,
+                Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), Some("FBSOUND")))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                3,
+                Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), Some("FCSOUND")))
+            )),
+            TraFragment::Entry(TraEntry::new(
+                4,
+                Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), Some("FDSOUND")))
+            )),
         ])
     )
 }
@@ -274,29 +307,29 @@ fn with_all_elements_in_tra_entry_and_eol_comment() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry {
-                id: 1,
-                content: Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"),
+            TraFragment::Entry(TraEntry::new(
+                1,
+                Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"),
                                                         Some(tilde!("aab")), Some("FASOUND")))
-            }),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string())),
-            TraFragment::Entry(TraEntry {
-                id: 2,
-                content: Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), Some("FBSOUND")))
-            }),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string())),
-            TraFragment::Entry(TraEntry {
-                id: 3,
-                content: Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), Some("FCSOUND")))
-            }),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string())),
-            TraFragment::Entry(TraEntry {
-                id: 4,
-                content: Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), Some("FDSOUND")))
-            }),
-            TraFragment::Comment(TraComment::EndOfLine(" comment 4".to_string())),
+            )),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 1".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(
+                2,
+                Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), Some("FBSOUND")))
+            )),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 2".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(
+                3,
+                Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), Some("FCSOUND")))
+            )),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 3".to_string()), Span::default()),
+            TraFragment::Entry(TraEntry::new(
+                4,
+                Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), Some("FDSOUND")))
+            )),
+            TraFragment::Comment(TraComment::EndOfLine(" comment 4".to_string()), Span::default()),
         ])
     )
 }
@@ -309,17 +342,469 @@ fn five_tildes_strings() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        parse_trafile(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry { id: 1, content: Explicit(ExplicitTraEntry::simplest(ftildes!("")))}),
-            TraFragment::Entry(TraEntry { id: 2, content: Explicit(ExplicitTraEntry::simplest(ftildes!(" ")))}),
-            TraFragment::Entry(TraEntry { id: 3, content: Explicit(ExplicitTraEntry::with_female(tilde!(" "),tilde!("")))}),
-            TraFragment::Error
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(ftildes!(""))))),
+            TraFragment::Entry(TraEntry::new(2, Explicit(ExplicitTraEntry::simplest(ftildes!(" "))))),
+            TraFragment::Entry(TraEntry::new(3, Explicit(ExplicitTraEntry::with_female(tilde!(" "),tilde!(""))))),
         ])
+    );
+    // the first five of the last eight tildes open a string that is never closed, the next two are an empty string
+    assert_eq!(lex_errors(&errors, input), vec![
+        (LexErrorKind::UnclosedFiveTildes, "~~~~~"),
+        (LexErrorKind::InvalidToken, "~"),
+    ]);
+}
+
+/// Tokens of weidu strings in edge cases
+#[test]
+fn five_tildes_edge_cases() {
+    use logos::Logos;
+
+    let five = |content: &str| Ok(Token::FiveTildesString(content.to_string()));
+    let tilde = |content: &str| Ok(Token::TildeString(content.to_string()));
+    let cases = [
+        // plain five tildes strings
+        ("~~~~~abc~~~~~", vec![five("abc")]),
+        ("~~~~~a~b~~c~~~d~~~~e~~~~~", vec![five("a~b~~c~~~d~~~~e")]),
+        ("~~~~~ \"quoted\" and %percent% ~~~~~", vec![five(" \"quoted\" and %percent% ")]),
+        ("~~~~~line 1\r\nline 2~~~~~", vec![five("line 1\nline 2")]),
+        // five tildes are longer than an empty `~` string
+        ("~~~~~~~~~~", vec![five("")]),
+        ("~~~~~ ~~~~~", vec![five(" ")]),
+        // less than five tildes are empty `~` strings
+        ("~~~~", vec![tilde(""), tilde("")]),
+        ("~~~~ ~~", vec![tilde(""), tilde(""), tilde("")]),
+        // the string is opened by the first five tildes and closed by the next five ones
+        ("~~~~~~abc~~~~~", vec![five("~abc")]),
+        ("~~~~~~~~abc~~~~~", vec![five("~~~abc")]),
+        ("~~~~~abc~~~~~~~", vec![five("abc"), tilde("")]),
+        ("~~~~~a~~~~~~~~~~b~~~~~", vec![five("a"), five("b")]),
+        ("~~~~~~~~~~~abc~~~~~", vec![five(""), tilde("abc"), tilde(""), tilde("")]),
+        // a string opened with five tildes and never closed
+        ("~~~~~ ~abc~ ~~~~", vec![Err(LexErrorKind::UnclosedFiveTildes), tilde("abc"), tilde(""), tilde("")]),
+        ("~~~~~~~~~", vec![Err(LexErrorKind::UnclosedFiveTildes), tilde(""), tilde("")]),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(Token::lexer(input).collect::<Vec<_>>(), expected, "lexing {input:?}");
+    }
+}
+
+#[test]
+fn same_shape_with_different_texts() {
+    let first = TraEntry::new(1, Explicit(ExplicitTraEntry::with_sound(tilde!("aaa"), "ASOUND")));
+    let second = TraEntry::new(1, Explicit(ExplicitTraEntry::with_sound(dquote!("bbb"), "BSOUND")));
+
+    assert_eq!(compare_shapes(&first, &second), vec![]);
+}
+
+#[test]
+fn shape_with_different_kinds() {
+    let first = TraEntry::new(1, Explicit(ExplicitTraEntry::with_female(tilde!("aaa"), tilde!("aab"))));
+    let second = TraEntry::new(1, TraEntryContent::Tlk(1234));
+
+    assert_eq!(compare_shapes(&first, &second), vec![ShapeMismatch::Kind(EntryKind::Explicit, EntryKind::Tlk)]);
+}
+
+#[test]
+fn shape_with_missing_female_variant_and_sounds() {
+    let first = TraEntry::new(
+        1,
+        Explicit(ExplicitTraEntry::new(tilde!("aaa"), None, Some(tilde!("aab")), Some("FASOUND")))
+    );
+    let second = TraEntry::new(1, Explicit(ExplicitTraEntry::with_sound(tilde!("aaa"), "ASOUND")));
+
+    assert_eq!(
+        compare_shapes(&first, &second),
+        vec![
+            ShapeMismatch::Sound(false, true),
+            ShapeMismatch::FemaleVariant(true, false),
+            ShapeMismatch::FemaleSound(true, false),
+        ]
+    );
+}
+
+#[test]
+fn display_entries() {
+    let entries = [
+        TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))),
+        TraEntry::new(
+            -2,
+            Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(percent!("ccc")), Some("CSOUND")))
+        ),
+        TraEntry::new(3, Explicit(ExplicitTraEntry::with_female(ftildes!("a~b"), tilde!("c")))),
+        TraEntry::new(4, TraEntryContent::At(1)),
+        TraEntry::new(5, TraEntryContent::Tlk(1234)),
+    ];
+
+    assert_eq!(
+        entries.iter().map(|entry| entry.to_string()).collect::<Vec<_>>(),
+        vec![
+            "@1 = ~aaa~",
+            r#"@-2 = "bbb" [BSOUND] %ccc% [CSOUND]"#,
+            "@3 = ~~~~~a~b~~~~~ ~c~",
+            "@4 = @1",
+            "@5 = #1234",
+        ]
+    )
+}
+
+fn assert_round_trip(input: &str) {
+    let mut errors = Vec::new();
+    let parsed = parse_trafile(&mut errors, input).unwrap();
+    assert!(errors.is_empty());
+
+    let written = write_trafile(&parsed);
+    let reparsed = parse_trafile(&mut errors, &written).unwrap();
+    assert!(errors.is_empty());
+
+    assert_eq!(without_spans(reparsed), without_spans(parsed));
+}
+
+#[test]
+fn write_trafile_keeps_delimiters_and_comments() {
+    let input = r#"
+    // comment 1
+    @1 = ~aaa~ [ASOUND] "aab" [FASOUND] /* comment 2 */
+    @2 = %b% ^ ~~~~~c~c~~~~~ ^ "d"
+    @3 = @1 @4 = #1234
+    "#;
+
+    let mut errors = Vec::new();
+    let parsed = parse_trafile(&mut errors, input).unwrap();
+
+    assert_eq!(
+        write_trafile(&parsed),
+        r#"// comment 1
+@1 = ~aaa~ [ASOUND] "aab" [FASOUND]
+/* comment 2 */
+@2 = %b% ^ ~~~~~c~c~~~~~ ^ "d"
+@3 = @1
+@4 = #1234
+"#
     )
 }
 
-/* this is not implemented
+#[test]
+fn round_trip_string_variants() {
+    assert_round_trip(r#"
+    @1 = ~aaa~
+    @2 = "bbb"
+    @3 = %ccc%
+    @4 = ~~~~~abc~~abc~~~~~
+    @5 = ~~ @6 = "" @7 = %% @8 = ~~~~~~~~~~
+    @9 = ~"with" %other% delimiters~ @10 = "~tilde~" @11 = %"%
+    @-12 = ~multi
+    line~
+    "#);
+}
+
+#[test]
+fn round_trip_concatenations_and_sounds() {
+    assert_round_trip(r#"
+    @1 = ~aaa~ ^ "bbb" ^ %ccc% [ASOUND] ~ddd~ ^ ~~~~~e~e~~~~~ [FASOUND]
+    @2 = ~aaa~ [ASOUND]
+    @3 = ~aaa~ ~aab~ [FASOUND]
+    @4 = @3
+    @5 = #0
+    @6 = #1234
+    "#);
+}
+
+#[test]
+fn round_trip_comments() {
+    assert_round_trip(r#"
+    // comment 1
+    @1 = ~aaa~ // comment 2
+    /* comment
+       3 */
+    @2 = "bbb"/* comment 4 */// comment 5
+    /**/
+    //
+    "#);
+}
+
+#[test]
+fn spans_of_entries_strings_sounds_and_comments() {
+    let input = "// first\n@1 = ~aaa~ [SND] \"bbb\" [FSND]\n/* c */ @2 = #12\n";
+
+    let mut errors = Vec::new();
+    let fragments = parse_trafile(&mut errors, input).unwrap();
+    let ranges = fragments.iter()
+        .map(|fragment| match fragment {
+            TraFragment::Comment(_, span) => span.range(),
+            TraFragment::Entry(entry) => entry.span.range(),
+            TraFragment::Error { .. } => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    // an end of line comment includes its line break
+    assert_eq!(ranges, vec![0..9, 9..38, 39..46, 47..55]);
+    assert_eq!(&input[47..55], "@2 = #12");
+
+    let TraFragment::Entry(TraEntry { content: Explicit(explicit), .. }) = &fragments[1] else {
+        panic!("expected an explicit entry, got {:?}", fragments[1]);
+    };
+    assert_eq!(&input[explicit.spans.value.range()], "~aaa~");
+    assert_eq!(&input[explicit.spans.sound.unwrap().range()], "[SND]");
+    assert_eq!(&input[explicit.spans.alt_value.unwrap().range()], "\"bbb\"");
+    assert_eq!(&input[explicit.spans.alt_sound.unwrap().range()], "[FSND]");
+}
+
+#[test]
+fn spans_are_offsets_in_the_original_crlf_text() {
+    let input = "// comment\r\n@1 = ~a\r\nb~\r\n@2 = ~c~\r\n";
+
+    let mut errors = Vec::new();
+    let fragments = parse_trafile(&mut errors, input).unwrap();
+    let TraFragment::Entry(entry) = &fragments[2] else {
+        panic!("expected an entry, got {:?}", fragments[2]);
+    };
+    assert_eq!(&input[entry.span.range()], "@2 = ~c~");
+    assert_eq!(without_spans(fragments)[1], TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("a\nb"))))));
+}
+
+#[test]
+fn entries_are_equal_only_at_the_same_place() {
+    let fragments = parse_ok("@1 = /* a */ ~x~ [S]\n@1 = /* a */ ~x~ [S]\n@1 = ~x~ [S]\n");
+    let entries = fragments.iter().filter_map(TraFragment::as_entry).collect::<Vec<_>>();
+
+    assert_ne!(entries[0], entries[1]);
+    assert!(entries[0].same_content(entries[1]));
+    assert!(!entries[0].same_content(entries[2]));
+    assert!(entries[0].content.same_content(&entries[2].content));
+    assert_ne!(Span::new(0, 1), Span::new(5, 9));
+}
+
+/// The kind and the skipped text of the errors found by the lexer
+fn lex_errors<'a>(errors: &[TraError], input: &'a str) -> Vec<(LexErrorKind, &'a str)> {
+    errors.iter()
+        .filter_map(|error| match &error.kind {
+            TraErrorKind::Lex(kind) => Some((kind.clone(), &input[error.span.range()])),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn lexer_skips_invalid_input_and_goes_on() {
+    let input = "@1 = ~aaa~ $$\n@2 = #-1\n@3 = #99999999999999999999 ?\n@4 = ~ddd~\n";
+
+    let mut errors = Vec::new();
+    let fragments = without_spans(parse_trafile(&mut errors, input).unwrap());
+    assert_eq!(lex_errors(&errors, input), vec![
+        (LexErrorKind::InvalidToken, "$$"),
+        (LexErrorKind::IntegerOverflow, "#99999999999999999999"),
+        (LexErrorKind::InvalidToken, "?"),
+    ]);
+    // the errors are in file order, whatever found them
+    assert!(errors.windows(2).all(|pair| pair[0].span.start <= pair[1].span.start));
+    assert_eq!(fragments.first(), Some(&TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry::simplest(tilde!("aaa")))))));
+    assert_eq!(fragments.last(), Some(&TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry::simplest(tilde!("ddd")))))));
+}
+
+#[test]
+fn recovery_resyncs_at_the_next_entry() {
+    let input = "@1 = ~aaa~\n@2 = [SND] ~bbb~ @3 ~ccc~\n@4 = ~ddd~ [S] ~f~ [FS]\n~orphan~ ~text~\n@5 = @6\n";
+
+    let mut errors = Vec::new();
+    let fragments = parse_trafile(&mut errors, input).unwrap();
+    let summary = fragments.iter()
+        .map(|fragment| match fragment {
+            TraFragment::Entry(entry) => format!("entry {}", entry.id),
+            TraFragment::Error { span, id } => format!("error {id:?} {:?}", &input[span.range()]),
+            TraFragment::Comment(..) => "comment".to_string(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(summary, vec![
+        "entry 1",
+        "error Some(2) \"@2 = [SND] ~bbb~ @3 ~ccc~\"",
+        "entry 4",
+        "error None \"~orphan~ ~text~\"",
+        "entry 5",
+    ]);
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn diagnostic_rendering() {
+    let input = "@1 = ~aaa~\n\t@2 = [SND] ~bbb~\n";
+
+    let mut errors = Vec::new();
+    parse_trafile(&mut errors, input).unwrap();
+    let rendered = errors.iter()
+        .map(|error| Diagnostic::from_error(error).render(input, "setup.tra"))
+        .collect::<Vec<_>>();
+    assert_eq!(rendered, vec![
+        "error[E010]: expected a string, a reference to another entry @123 or a reference to dialog.tlk #123, \
+         found a sound reference ([SND])\n \
+         --> setup.tra:2:7\n  \
+         |\n\
+         2 | \t@2 = [SND] ~bbb~\n  \
+         | \t     ^^^^^\n  \
+         = hint: the sound reference comes after the string",
+    ]);
+}
+
+#[test]
+fn diagnostic_rendering_with_a_span_outside_the_source() {
+    let past_end = Diagnostic::error("E999", "problem", Span::new(40, 45)).render("@1 = ~é~\n", "x.tra");
+    assert!(past_end.contains("--> x.tra:2:1"), "{past_end}");
+
+    // inside the two bytes of é
+    let inside_char = Diagnostic::error("E999", "problem", Span::new(7, 8)).render("@1 = ~é~\n", "x.tra");
+    assert!(inside_char.contains("--> x.tra:1:1"), "{inside_char}");
+}
+
+#[test]
+fn errors_and_diagnostics_agree_on_columns() {
+    let input = "@1 = ~é~ é\n";
+
+    let mut errors = Vec::new();
+    parse_trafile(&mut errors, input).unwrap();
+    assert_eq!(errors[0].position, LinePosition::new(1, 10));
+    let rendered = Diagnostic::from_error(&errors[0]).render(input, "x.tra");
+    assert!(rendered.contains("--> x.tra:1:10"), "{rendered}");
+}
+
+#[test]
+fn diagnostic_of_lexer_error_at_end_of_file() {
+    let input = "@1 = ~aaa~\n@2 = #99999999999999999999";
+
+    let mut errors = Vec::new();
+    parse_trafile(&mut errors, input).unwrap();
+    let diagnostics = errors.iter()
+        .map(Diagnostic::from_error)
+        .map(|diagnostic| (diagnostic.code, diagnostic.span.range()))
+        .collect::<Vec<_>>();
+    // the skipped reference leaves the entry without a value
+    assert_eq!(diagnostics, vec![("E011", 15..15), ("E002", 16..37)]);
+}
+
+#[test]
+fn fragments_stay_in_file_order_around_errors() {
+    let inputs = [
+        "@1 = ~a~\n@2 = [SND] // sound first by mistake\n~b~\n@3 = ~c~\n",
+        "@1 = ~a~ /* x */ $ /* y */ ~z~ // w\n@2 = = /* v */ ~b~\n/* u */ @3 = ~c~",
+    ];
+    for input in inputs {
+        let fragments = parse_trafile(&mut Vec::new(), input).unwrap();
+        let spans = fragments.iter().map(TraFragment::span).collect::<Vec<_>>();
+        assert!(spans.windows(2).all(|pair| pair[0].end <= pair[1].start), "{input:?}: {spans:?}");
+    }
+
+    // the comment is in the skipped input
+    let fragments = parse_trafile(&mut Vec::new(), "@1 = ~a~\n@2 = [SND] // sound first by mistake\n~b~\n@3 = ~c~\n").unwrap();
+    assert!(matches!(fragments.as_slice(), [TraFragment::Entry(_), TraFragment::Error { .. }, TraFragment::Entry(_)]),
+            "{fragments:?}");
+}
+
+#[test]
+fn syntax_errors_with_position_and_expected_tokens() {
+    let input = "@1 = ~aaa~\n@2 = [SND] ~bbb~\n@3 = ~c~ $\n@4 =";
+
+    let mut errors = Vec::new();
+    parse_trafile(&mut errors, input).unwrap();
+    assert_eq!(
+        errors.iter()
+            .map(|error| (error.kind.clone(), error.position.clone(), error.found.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (TraErrorKind::UnexpectedToken, LinePosition::new(2, 6), Some("a sound reference ([SND])".to_string())),
+            (TraErrorKind::Lex(LexErrorKind::InvalidToken), LinePosition::new(3, 10), None),
+            (TraErrorKind::UnexpectedEndOfFile, LinePosition::new(4, 5), Some("the end of the file".to_string())),
+        ]
+    );
+    assert_eq!(errors[0].expected.as_deref(), Some("a string, a reference to another entry @123 or a reference to dialog.tlk #123"));
+    assert_eq!(errors[0].hint.as_deref(), Some("the sound reference comes after the string"));
+    assert_eq!(
+        errors[0].to_string(),
+        "expected a string, a reference to another entry @123 or a reference to dialog.tlk #123, \
+         found a sound reference ([SND]) at line 2, column 6"
+    );
+    assert_eq!(errors[1].to_string(), "unrecognized input at line 3, column 10");
+}
+
+/// Code, message and underlined text of the unclosed strings warnings
+fn unclosed_strings(input: &str) -> Vec<(&'static str, String, &str)> {
+    find_unclosed_strings(input).into_iter()
+        .map(|diagnostic| (diagnostic.code, diagnostic.message, &input[diagnostic.span.range()]))
+        .collect()
+}
+
+#[test]
+fn unclosed_strings_of_all_kinds() {
+    let message = |line: usize| format!("the string opened at line {line} is probably not closed");
+    assert_eq!(unclosed_strings("@1 = ~abc\n@2 = ~def~\n@3 = ~ghi~\n"), vec![("W001", message(1), "~")]);
+    assert_eq!(unclosed_strings("@1 = \"abc\n  @2 = \"def\"\n"), vec![("W001", message(1), "\"")]);
+    assert_eq!(unclosed_strings("@1 = ~a~\n@2 = %abc\n@-3=%def%\n"), vec![("W001", message(2), "%")]);
+    assert_eq!(unclosed_strings("@1 = ~~~~~abc\n@2 = ~~~~~def~~~~~\n"), vec![("W001", message(1), "~~~~~")]);
+}
+
+#[test]
+fn unclosed_strings_ignore_normal_multiline_strings() {
+    assert_eq!(unclosed_strings("@1 = ~first line\n@ the second line = ~\n@2 = ~a~ @3\n"), vec![]);
+}
+
+#[test]
+fn unclosed_strings_with_too_many_lines() {
+    let input = format!("@1 = ~abc\n{}~", "a line\n".repeat(60));
+    assert_eq!(
+        find_unclosed_strings(&input).into_iter().map(|diagnostic| (diagnostic.code, diagnostic.hint)).collect::<Vec<_>>(),
+        vec![("W002", Some("it is 61 lines long".to_string()))],
+    );
+}
+
+#[test]
+fn string_expressions_with_references() {
+    let input = "@1 = ~a~ ^ @2 ^ #-3\n@4 = @5 [SND] #6\n@7 = #8 ^ %b% [S1] @9 [S2]\n@10 = #-1\n@11 = @-12\n";
+
+    let lit = |lit| Box::new(WeiduString::Literal(lit));
+    let mut errors = Vec::new();
+
+    assert_eq!(
+        parse_trafile(&mut errors, input).map(without_spans),
+        Ok(vec![
+            TraFragment::Entry(TraEntry::new(1, Explicit(ExplicitTraEntry {
+                value: WeiduString::Concat(
+                    Box::new(WeiduString::Concat(lit(tilde!("a")), Box::new(WeiduString::At(2)))),
+                    Box::new(WeiduString::Ref(-3)),
+                ),
+                ..Default::default()
+            }))),
+            TraFragment::Entry(TraEntry::new(4, Explicit(ExplicitTraEntry {
+                value: WeiduString::At(5),
+                sound: Some("SND".to_string()),
+                alt_value: Some(WeiduString::Ref(6)),
+                ..Default::default()
+            }))),
+            TraFragment::Entry(TraEntry::new(7, Explicit(ExplicitTraEntry {
+                value: WeiduString::Concat(Box::new(WeiduString::Ref(8)), lit(percent!("b"))),
+                sound: Some("S1".to_string()),
+                alt_value: Some(WeiduString::At(9)),
+                alt_sound: Some("S2".to_string()),
+                ..Default::default()
+            }))),
+            TraFragment::Entry(TraEntry::new(10, TraEntryContent::Tlk(-1))),
+            TraFragment::Entry(TraEntry::new(11, TraEntryContent::At(-12))),
+        ])
+    );
+    assert_eq!(errors, vec![]);
+}
+
+#[test]
+fn round_trip_string_expressions() {
+    assert_round_trip("@1 = ~a~ ^ @2 ^ #-3\n@4 = @5 [SND] #6\n@7 = #8 ^ %b% [S1] @9 ^ ~~~~~c~~~~~ [S2]\n@10 = #-1\n@11 = @-12\n");
+}
+
+fn with_comments(mut entry: TraEntry, comments: &[TraComment]) -> TraEntry {
+    entry.comments = comments.iter().map(|comment| (comment.clone(), Span::default())).collect();
+    entry
+}
+
+fn enclosed(content: &str) -> TraComment {
+    TraComment::Enclosed(content.to_string())
+}
+
 #[test]
 fn with_all_elements_in_tra_entry_and_middle_of_line_comment() {
     let input = r#"
@@ -332,25 +817,457 @@ fn with_all_elements_in_tra_entry_and_middle_of_line_comment() {
     let mut errors = Vec::new();
 
     assert_eq!(
-        TraFileParser::new().parse(&mut errors, &input),
+        parse_trafile(&mut errors, input).map(without_spans),
         Ok(vec![
-            TraFragment::Entry(TraEntry {
-                id: 1,
-                content: Explicit(ExplicitTraEntry::new("aaa", Some("ASOUND"), Some("aab"), Some("FASOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 2,
-                content: Explicit(ExplicitTraEntry::new("bbb", Some("BSOUND"), Some("bbc"), Some("FBSOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 3,
-                content: Explicit(ExplicitTraEntry::new("ccc", Some("CSOUND"), Some("ccd"), Some("FCSOUND")))
-            }),
-            TraFragment::Entry(TraEntry {
-                id: 4,
-                content: Explicit(ExplicitTraEntry::new("abc~~abc", Some("DSOUND"), Some("bca~~bca"), Some("FDSOUND")))
-            }),
+            TraFragment::Entry(with_comments(TraEntry::new(
+                1,
+                Explicit(ExplicitTraEntry::new(tilde!("aaa"), Some("ASOUND"), Some(tilde!("aab")), Some("FASOUND")))
+            ), &[enclosed(" comment 1 ")])),
+            TraFragment::Entry(with_comments(TraEntry::new(
+                2,
+                Explicit(ExplicitTraEntry::new(dquote!("bbb"), Some("BSOUND"), Some(dquote!("bbc")), Some("FBSOUND")))
+            ), &[enclosed(" comment 2 ")])),
+            TraFragment::Entry(with_comments(TraEntry::new(
+                3,
+                Explicit(ExplicitTraEntry::new(percent!("ccc"), Some("CSOUND"), Some(percent!("ccd")), Some("FCSOUND")))
+            ), &[enclosed(" comment 3 ")])),
+            TraFragment::Entry(with_comments(TraEntry::new(
+                4,
+                Explicit(ExplicitTraEntry::new(ftildes!("abc~~abc"), Some("DSOUND"), Some(ftildes!("bca~~bca")), Some("FDSOUND")))
+            ), &[enclosed(" comment 4 ")])),
         ])
-    )
+    );
+    assert_eq!(errors, vec![]);
+}
+
+#[test]
+fn comments_around_the_equal_sign_and_references() {
+    let input = "@1 /* a */ = /* b */ @2 // c\n@3 = // d\n#12 /* between */ @4 = ~x~ ^ /* e */ ~y~\n";
+
+    let mut errors = Vec::new();
+
+    assert_eq!(
+        parse_trafile(&mut errors, input).map(without_spans),
+        Ok(vec![
+            TraFragment::Entry(with_comments(TraEntry::new(1, TraEntryContent::At(2)), &[enclosed(" a "), enclosed(" b ")])),
+            TraFragment::Comment(TraComment::EndOfLine(" c".to_string()), Span::default()),
+            TraFragment::Entry(with_comments(TraEntry::new(3, TraEntryContent::Tlk(12)), &[TraComment::EndOfLine(" d".to_string())])),
+            TraFragment::Comment(enclosed(" between "), Span::default()),
+            TraFragment::Entry(with_comments(TraEntry::new(4, Explicit(ExplicitTraEntry {
+                value: WeiduString::Concat(Box::new(WeiduString::Literal(tilde!("x"))), Box::new(WeiduString::Literal(tilde!("y")))),
+                ..Default::default()
+            })), &[enclosed(" e ")])),
+        ])
+    );
+    assert_eq!(errors, vec![]);
+}
+
+#[test]
+fn round_trip_inner_comments() {
+    assert_round_trip("@1 /* a */ = /* b */ ~x~ // c\n [S] /* d */ ~y~ ^ /* e */ ~z~ // f\n [FS]\n@2 = // g\n#3\n");
+}
+
+
+fn parse_ok(input: &str) -> Vec<TraFragment> {
+    let mut errors = Vec::new();
+    let fragments = parse_trafile(&mut errors, input).expect("valid input");
+    assert_eq!(errors, vec![]);
+    fragments
+}
+
+fn texts(male: &str, female: &str) -> EvaluatedText {
+    EvaluatedText { male: male.to_string(), female: female.to_string() }
+}
+
+#[test]
+fn evaluate_references_and_concatenations() {
+    let fragments = parse_ok("@1 = ~Hello~ ~Hi~\n@2 = @1\n@3 = @1 ^ ~, friend~ [SND]\n@4 = #12 ^ ~!~\n@5 = ~old~\n@5 = ~new~\n");
+    let others = parse_ok("@10 = ~elsewhere~\n@1 = ~not used~\n");
+    let tlk = std::collections::HashMap::from([(12, "From tlk".to_string())]);
+
+    let evaluator = Evaluator::new(&fragments).with_file(&others).with_tlk(&tlk);
+    let evaluation = evaluator.evaluate_all();
+
+    assert_eq!(evaluation.diagnostics, vec![]);
+    assert_eq!(evaluation.texts.into_iter().collect::<Vec<_>>(), vec![
+        (1, texts("Hello", "Hi")),
+        (2, texts("Hello", "Hi")),
+        (3, texts("Hello, friend", "Hi, friend")),
+        (4, texts("From tlk!", "From tlk!")),
+        (5, texts("new", "new")),
+    ]);
+    assert_eq!(evaluator.evaluate(10), Ok(texts("elsewhere", "elsewhere")));
+    // without dialog.tlk, tlk references are kept as they are
+    assert_eq!(Evaluator::new(&fragments).evaluate(4), Ok(texts("#12!", "#12!")));
+}
+
+#[test]
+fn evaluate_cycles_and_undefined_references() {
+    let input = "@1 = @2\n@2 = ~a~ ^ @3\n@3 = @1\n@4 = @4\n@5 = ~ok~ @9\n@6 = #7\n@7 = ~fine~\n";
+    let fragments = parse_ok(input);
+    let tlk = std::collections::BTreeMap::new();
+
+    let evaluator = Evaluator::new(&fragments).with_tlk(&tlk);
+    assert_eq!(evaluator.evaluate(2), Err(EvaluationError::Cycle(vec![2, 3, 1, 2])));
+    assert_eq!(evaluator.evaluate(4), Err(EvaluationError::Cycle(vec![4, 4])));
+    assert_eq!(evaluator.evaluate(5), Err(EvaluationError::UndefinedEntry(9)));
+    assert_eq!(evaluator.evaluate(6), Err(EvaluationError::UndefinedTlk(7)));
+
+    let evaluation = evaluator.evaluate_all();
+    assert_eq!(evaluation.texts.into_iter().collect::<Vec<_>>(), vec![(7, texts("fine", "fine"))]);
+    assert_eq!(
+        evaluation.diagnostics.iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.message.as_str(), diagnostic.span.range()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("E020", "the text of @1 can not be computed: cycle of references @1 → @2 → @3 → @1", 0..7),
+            ("E020", "the text of @2 can not be computed: cycle of references @2 → @3 → @1 → @2", 8..21),
+            ("E020", "the text of @3 can not be computed: cycle of references @3 → @1 → @2 → @3", 22..29),
+            ("E020", "the text of @4 can not be computed: cycle of references @4 → @4", 30..37),
+            ("E021", "the text of @5 can not be computed: reference to @9, which is not defined", 38..50),
+            ("E022", "the text of @6 can not be computed: reference to #7, which is not in dialog.tlk", 51..58),
+        ]
+    );
+}
+
+#[test]
+fn tra_file_indexes_entries_by_id() {
+    let file = TraFile::parse("@3 = ~c~\n@1 = ~a~\n// comment\n@2 = @1\n@1 = ~a2~\n@3 = ~c~\n").unwrap();
+    let ids = |entries: Vec<&TraEntry>| entries.iter().map(|entry| (entry.id, entry.content.to_string())).collect::<Vec<_>>();
+
+    assert_eq!(ids(file.entries().collect()), vec![
+        (3, "~c~".to_string()), (1, "~a~".to_string()), (2, "@1".to_string()), (1, "~a2~".to_string()), (3, "~c~".to_string()),
+    ]);
+    assert_eq!(ids(file.entries_by_id().collect()), vec![
+        (1, "~a~".to_string()), (1, "~a2~".to_string()), (2, "@1".to_string()), (3, "~c~".to_string()), (3, "~c~".to_string()),
+    ]);
+    assert_eq!(file.ids().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(file.get(1).map(|entry| entry.content.to_string()), Some("~a2~".to_string()));
+    assert_eq!(file.get(4), None);
+    assert!(file.contains(2) && !file.contains(4));
+    assert_eq!(file.get_all(1).count(), 2);
+    assert!(file.is_duplicated(3) && !file.is_duplicated(2));
+    assert_eq!(file.duplicates().map(|(id, entries)| (id, entries.len())).collect::<Vec<_>>(), vec![(1, 2), (3, 2)]);
+    assert_eq!(file.fragments().len(), 6);
+}
+
+#[test]
+fn tra_file_from_bytes() {
+    let file = TraFile::from_bytes(b"@1 = ~caf\xe9~\n@2 = ~x~ ~~\n@3 = ~y~", encoding_rs::WINDOWS_1252).unwrap();
+
+    assert_eq!(file.get(1).map(|entry| entry.content.to_string()), Some("~café~".to_string()));
+    assert!(!file.charset_replacements());
+    assert_eq!(file.damaged_ids(), vec![]);
+
+    let file = TraFile::from_bytes(b"@1 = ~caf\xe9~\n@2 = = ~x~\n", encoding_rs::UTF_8).unwrap();
+    assert!(file.charset_replacements());
+    assert_eq!(file.errors().len(), 1);
+    assert_eq!(file.damaged_ids(), vec![2]);
+
+    assert!(matches!(TraFile::load("does/not/exist.tra", encoding_rs::UTF_8), Err(LoadError::Io(_))));
+}
+
+#[test]
+fn tra_file_comments_of_entries() {
+    let file = TraFile::parse(concat!(
+        "// header\n",
+        "\n",
+        "// Imoen banter\n",
+        "/* first line */\n",
+        "@1 = ~a~ // trailing\n",
+        "@2 = ~b~\n",
+        "// on its own\n",
+        "\n",
+        "@3 = ~c~ /* same line */ // next one\n",
+        "@4 = ~d~\n",
+        "@5 = = broken\n",
+        "// before the error\n",
+        "@6 = ~f~\n",
+    )).unwrap();
+    let contents = |id| file.comments(id)
+        .map(|comments| comments.iter().map(TraComment::content).collect::<Vec<_>>());
+
+    assert_eq!(contents(1), Some(vec![" Imoen banter", " first line ", " trailing"]));
+    assert_eq!(file.comments(1).map(|comments| comments.leading.len()), Some(2));
+    assert_eq!(contents(2), Some(vec![]));
+    assert_eq!(contents(3), Some(vec![" same line ", " next one"]));
+    assert_eq!(contents(4), Some(vec![]));
+    assert_eq!(contents(6), Some(vec![" before the error"]));
+    assert_eq!(contents(7), None);
+    assert!(file.comments(2).is_some_and(EntryComments::is_empty));
+}
+
+#[test]
+fn comments_around_a_syntax_error() {
+    let source = "@1 = ~a~\n@2 = [SND] // sound first by mistake\n~b~\n@3 = ~c~\n";
+    let file = TraFile::parse(source).unwrap();
+    assert!(file.comments(1).is_some_and(EntryComments::is_empty));
+    assert!(file.comments(3).is_some_and(EntryComments::is_empty));
+
+    let doc = document(source);
+    assert!(doc.comments(3).is_some_and(EntryComments::is_empty));
+    assert_eq!(format_trafile(source, FormatOptions::default()).map_err(|errors| errors.len()), Err(1));
+}
+
+#[test]
+fn comments_of_every_definition_and_in_documents() {
+    let source = "// first\n@1 = ~a~\n// second\n@1 = ~b~ // after\n";
+    let file = TraFile::parse(source).unwrap();
+    fn contents(comments: Option<&EntryComments>) -> Option<Vec<&str>> {
+        comments.map(|comments| comments.iter().map(TraComment::content).collect())
+    }
+
+    let definitions = file.get_all(1).map(|entry| contents(file.comments_of(entry))).collect::<Vec<_>>();
+    assert_eq!(definitions, vec![Some(vec![" first"]), Some(vec![" second", " after"])]);
+    assert_eq!(contents(file.comments_of(&TraEntry::new(1, TraEntryContent::Tlk(1)))), None);
+
+    let mut doc = document(source);
+    assert_eq!(contents(doc.comments(1)), Some(vec![" second", " after"]));
+    doc.insert_entry(2, TraEntryContent::Tlk(2)).unwrap();
+    doc.insert_comment_before(2, &TraComment::EndOfLine(" about 2".to_string())).unwrap();
+    assert_eq!(contents(doc.comments(2)), Some(vec![" about 2"]));
+    assert_eq!(contents(doc.comments(3)), None);
+}
+
+#[test]
+fn cst_is_lossless() {
+    let inputs = [
+        "",
+        "  \n",
+        "@1 = ~a~\n",
+        "// header\r\n\r\n@1   =\t~a~ [S]   \"b\"  // trailing\r\n/* between */\n@2 = @1\n\n\n@3 = #12 ^ %c%",
+        "@1 /* a */ = /* b */ ~x~ // c\n [S] ~y~ ^ ~z~\n@2 = $$ ~~~~~five~~~~~\n@3 = [SND] ~broken~\n~orphan~\n@4 = ~~~~~ unclosed",
+    ];
+    for input in inputs {
+        let mut cst_errors = Vec::new();
+        let cst = Cst::parse(&mut cst_errors, input).unwrap();
+        assert_eq!(cst.to_string(), input);
+        assert_eq!(cst.tokens().iter().map(|token| token.text.as_str()).collect::<String>(), input);
+        for node in cst.nodes() {
+            assert_eq!(cst.node_text(node), &input[node.fragment.span().range()]);
+        }
+
+        // the fragments parsed from the tokens are the ones parsed from the source
+        let mut errors = Vec::new();
+        assert_eq!(cst.fragments().cloned().collect::<Vec<_>>(), parse_trafile(&mut errors, input).unwrap());
+        assert_eq!(cst_errors, errors);
+    }
+}
+
+#[test]
+fn cst_tokens_and_fragments() {
+    let input = "@1 = ~a~ // c\n\n@2 = ? ~b~\n";
+
+    let cst = Cst::parse(&mut Vec::new(), input).unwrap();
+    assert_eq!(
+        cst.tokens().iter()
+            .map(|token| (token.text.as_str(), token.is_trivia()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("@1", false), (" ", true), ("=", false), (" ", true), ("~a~", false), (" ", true), ("// c\n", true),
+            ("\n", true), ("@2", false), (" ", true), ("=", false), (" ", true), ("?", true), (" ", true), ("~b~", false),
+            ("\n", true),
+        ]
+    );
+    assert_eq!(cst.tokens()[12].kind, CstTokenKind::Invalid(LexErrorKind::InvalidToken));
+    assert_eq!(
+        cst.nodes().iter().map(|node| (node.tokens.clone(), cst.node_text(node))).collect::<Vec<_>>(),
+        vec![(0..5, "@1 = ~a~".to_string()), (6..7, "// c\n".to_string()), (8..15, "@2 = ? ~b~".to_string())]
+    );
+    assert_eq!(cst.fragments().filter_map(TraFragment::as_entry).map(|entry| entry.id).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+fn document(source: &str) -> TraDocument {
+    TraDocument::parse(&mut Vec::new(), source).unwrap()
+}
+
+#[test]
+fn set_entry_rewrites_only_what_changes() {
+    let mut doc = document("// header\n@1   =  \"old\" /* keep */ [SND] ~female~ // trailing\n@2 = %two%\n@3 = @2\n");
+
+    doc.set_text(1, "new").unwrap();
+    assert_eq!(doc.to_string(), "// header\n@1   =  \"new\" /* keep */ [SND] ~female~ // trailing\n@2 = %two%\n@3 = @2\n");
+
+    // the delimiter changes only when the text contains it
+    doc.set_text(2, "50%").unwrap();
+    assert_eq!(doc.get(2).unwrap().content.to_string(), "~50%~");
+
+    doc.set_entry(1, Explicit(ExplicitTraEntry::new(tilde!("new"), Some("OTHER"), Some(tilde!("female")), None))).unwrap();
+    assert_eq!(doc.to_string(), "// header\n@1   =  \"new\" /* keep */ [OTHER] ~female~ // trailing\n@2 = ~50%~\n@3 = @2\n");
+
+    // a different layout rewrites the whole content
+    doc.set_entry(1, Explicit(ExplicitTraEntry::simplest(tilde!("alone")))).unwrap();
+    assert_eq!(doc.to_string(), "// header\n@1   =  \"alone\" // trailing\n@2 = ~50%~\n@3 = @2\n");
+
+    doc.set_text(3, "was a reference").unwrap();
+    assert_eq!(doc.to_string(), "// header\n@1   =  \"alone\" // trailing\n@2 = ~50%~\n@3 = ~was a reference~\n");
+
+    assert_eq!(doc.set_text(4, "x"), Err(EditError::UnknownId(4)));
+}
+
+#[test]
+fn insert_entries_in_id_order() {
+    let mut doc = document("/* header */\r\n\r\n@10 = %ten% // c\r\n@20 = %twenty%\r\n@30 = %thirty%");
+
+    doc.insert_entry(15, Explicit(ExplicitTraEntry::simplest(tilde!("fifteen")))).unwrap();
+    doc.insert_entry(5, Explicit(ExplicitTraEntry::simplest(tilde!("five")))).unwrap();
+    doc.insert_entry(40, TraEntryContent::Tlk(12)).unwrap();
+    doc.insert_entry_after(20, 25, Explicit(ExplicitTraEntry::with_female(tilde!("m"), tilde!("f")))).unwrap();
+    assert_eq!(
+        doc.to_string(),
+        "/* header */\r\n\r\n@5 = %five%\r\n@10 = %ten% // c\r\n@15 = %fifteen%\r\n@20 = %twenty%\r\n\
+         @25 = %m% %f%\r\n@30 = %thirty%\r\n@40 = #12"
+    );
+    assert_eq!(doc.insert_entry(10, TraEntryContent::At(1)), Err(EditError::AlreadyDefined(10)));
+    assert_eq!(doc.insert_entry_after(99, 100, TraEntryContent::At(1)), Err(EditError::UnknownId(99)));
+
+    let mut doc = document("");
+    doc.insert_entry(1, Explicit(ExplicitTraEntry::simplest(tilde!("first")))).unwrap();
+    assert_eq!(doc.to_string(), "@1 = ~first~\n");
+}
+
+#[test]
+fn inserted_entries_do_not_take_the_comments_of_others() {
+    let mut doc = document("// Imoen banter\n@2 = ~bb~");
+    doc.insert_entry(1, Explicit(ExplicitTraEntry::simplest(tilde!("a")))).unwrap();
+    assert_eq!(doc.to_string(), "@1 = ~a~\n// Imoen banter\n@2 = ~bb~");
+    assert!(doc.comments(1).is_some_and(EntryComments::is_empty));
+    assert_eq!(doc.comments(2).map(|comments| comments.leading.len()), Some(1));
+
+    // a comment after an entry on its line belongs to this entry
+    let mut doc = document("@3 = ~c~ /* about 3 */ @4 = ~d~");
+    doc.insert_entry(1, TraEntryContent::Tlk(1)).unwrap();
+    assert_eq!(doc.to_string(), "@1 = #1\n@3 = ~c~ /* about 3 */ @4 = ~d~");
+}
+
+#[test]
+fn comments_at_the_end_of_a_line_stay_with_their_entry() {
+    let mut doc = document("@1 = ~a~ /* about 1 */\n@3 = ~c~ /* c */ @4 = ~d~\n@5 = ~e~ /* e */ // e");
+    doc.insert_entry(2, Explicit(ExplicitTraEntry::simplest(tilde!("b")))).unwrap();
+    doc.insert_entry_after(3, 30, TraEntryContent::Tlk(30)).unwrap();
+    doc.insert_entry(6, TraEntryContent::Tlk(6)).unwrap();
+    assert_eq!(
+        doc.to_string(),
+        "@1 = ~a~ /* about 1 */\n@2 = ~b~\n@3 = ~c~ @30 = #30 /* c */ @4 = ~d~\n@5 = ~e~ /* e */ // e\n@6 = #6"
+    );
+
+    doc.remove_entry(1).unwrap();
+    doc.remove_entry(5).unwrap();
+    assert_eq!(doc.to_string(), "@2 = ~b~\n@3 = ~c~ @30 = #30 /* c */ @4 = ~d~\n@6 = #6");
+}
+
+#[test]
+fn remove_entries() {
+    let mut doc = document("// about 1\n@1 = ~one~ // trailing\n  @2 = ~two~\n@3 = ~a~ @4 = ~b~ @5 = ~c~\n@2 = ~again~\n@6 = ~six~");
+
+    doc.remove_entry(1).unwrap();
+    doc.remove_entry(2).unwrap();
+    doc.remove_entry(4).unwrap();
+    doc.remove_entry(5).unwrap();
+    assert_eq!(doc.to_string(), "@3 = ~a~\n@6 = ~six~");
+    doc.remove_entry(6).unwrap();
+    assert_eq!(doc.to_string(), "@3 = ~a~\n");
+    assert_eq!(doc.remove_entry(6), Err(EditError::UnknownId(6)));
+}
+
+#[test]
+fn removed_entries_take_their_comments_with_them() {
+    let mut doc = document("// about 1\n@1 = ~a~\n@2 = ~b~");
+    doc.remove_entry(1).unwrap();
+    assert_eq!(doc.to_string(), "@2 = ~b~");
+    assert!(doc.comments(2).is_some_and(EntryComments::is_empty));
+
+    // a comment with an empty line after it documents no entry, it stays
+    let mut doc = document("// loose\n\n/* about 1 */ /* more */\n@1 = ~a~ @2 = ~b~ /* x */ @3 = ~c~");
+    doc.remove_entry(1).unwrap();
+    doc.remove_entry(3).unwrap();
+    assert_eq!(doc.to_string(), "// loose\n\n@2 = ~b~ /* x */");
+}
+
+#[test]
+fn edits_never_lose_data() {
+    let source = "@1 = ~a~ ^ ~b~\n@2 = ~z~\n";
+    let mut doc = document(source);
+
+    // every part of a concatenation gets delimiters that can hold it
+    let concat = WeiduString::Concat(Box::new(WeiduString::Literal(tilde!("a~"))), Box::new(WeiduString::Literal(tilde!("b"))));
+    doc.set_entry(1, Explicit(ExplicitTraEntry { value: concat, ..Default::default() })).unwrap();
+    assert_eq!(doc.to_string(), "@1 = \"a~\" ^ ~b~\n@2 = ~z~\n");
+    assert_eq!(doc.get(1).unwrap().content.text(), "a~b");
+
+    let mut doc = document(source);
+    let female = Explicit(ExplicitTraEntry::with_female(tilde!("m"), tilde!("f~")));
+    doc.set_entry(2, female).unwrap();
+    assert_eq!(doc.to_string(), "@1 = ~a~ ^ ~b~\n@2 = ~m~ \"f~\"\n");
+
+    assert_eq!(
+        doc.set_entry(2, Explicit(ExplicitTraEntry::with_sound(tilde!("z"), "X]Y"))),
+        Err(EditError::InvalidSound("X]Y".to_string()))
+    );
+    assert_eq!(
+        doc.insert_entry(3, Explicit(ExplicitTraEntry::new(tilde!("z"), None, Some(tilde!("f")), Some("")))),
+        Err(EditError::InvalidSound(String::new()))
+    );
+
+    // a comment closing itself too early would leave text that can not be read
+    let result = doc.insert_comment_before(2, &TraComment::Enclosed("a */ b".to_string()));
+    assert!(matches!(result, Err(EditError::SyntaxErrors(_))), "{result:?}");
+    assert_eq!(doc.to_string(), "@1 = ~a~ ^ ~b~\n@2 = ~m~ \"f~\"\n");
+}
+
+#[test]
+fn edits_are_allowed_in_files_with_errors() {
+    let mut doc = TraDocument::parse(&mut Vec::new(), "@1 = ~a~ ?\n@2 = ~b~\n").unwrap();
+    doc.set_text(2, "c").unwrap();
+    assert_eq!(doc.to_string(), "@1 = ~a~ ?\n@2 = ~c~\n");
+}
+
+#[test]
+fn literal_delimiters_for_a_content() {
+    assert_eq!(tilde!("").with_content("a ~b~ c"), Some(ftildes!("a ~b~ c")));
+    assert_eq!(dquote!("").with_content("say \"hi\""), Some(tilde!("say \"hi\"")));
+    assert_eq!(ftildes!("").with_content("ends with ~"), Some(dquote!("ends with ~")));
+    assert_eq!(percent!("").with_content("~~~~~ \" % ~"), None);
+}
+
+fn formatted(source: &str, sort: bool, normalize_delimiters: bool) -> String {
+    let options = FormatOptions { sort, normalize_delimiters };
+    let formatted = format_trafile(source, options).unwrap();
+    assert_eq!(format_trafile(&formatted, options).unwrap(), formatted, "formatting is stable");
+    formatted
+}
+
+#[test]
+fn format_spacing_and_lines() {
+    let source = "// header\n\n\n@2=~b~   [SND]\"f\" // about 2\n@1   =\n  %a% @3 = /* inner */ #12\n\n\n/* loose */\n\n@4 = ~x~ ^\t~y~";
+    assert_eq!(
+        formatted(source, false, false),
+        "// header\n\n@2 = ~b~ [SND] \"f\" // about 2\n@1 = %a%\n@3 = /* inner */ #12\n\n/* loose */\n\n@4 = ~x~ ^ ~y~\n"
+    );
+}
+
+#[test]
+fn format_sorted_with_comments() {
+    let source = "/* header */\n\n// about 3\n@3 = ~c~ // trailing 3\n@1 = ~a~\n\n// loose\n\n// about 2\n// more about 2\n@2 = ~b~\n// end\n";
+    assert_eq!(
+        formatted(source, true, false),
+        "/* header */\n\n@1 = ~a~\n\n// loose\n\n// about 2\n// more about 2\n@2 = ~b~\n\n// about 3\n@3 = ~c~ // trailing 3\n// end\n"
+    );
+}
+
+#[test]
+fn format_sorted_with_several_comments_after_an_entry() {
+    let source = "@2 = ~b~ /* x */ // y\n@1 = ~a~ /* z */ /* w */\n";
+    assert_eq!(formatted(source, true, false), "@1 = ~a~ /* z */ /* w */\n@2 = ~b~ /* x */ // y\n");
+}
+
+#[test]
+fn format_with_normalized_delimiters() {
+    let source = "@1 = \"a\" %b%\r\n@2 = %with ~tilde~ in it% ^ ~~~~~plain~~~~~\r\n@3 = \"~\"\r\n";
+    assert_eq!(
+        formatted(source, false, true),
+        "@1 = ~a~ ~b~\r\n@2 = ~~~~~with ~tilde~ in it~~~~~ ^ ~plain~\r\n@3 = \"~\"\r\n"
+    );
+    let errors = format_trafile("@1 = [S] ~a~\n", FormatOptions::default()).unwrap_err();
+    assert_eq!(errors.iter().map(|error| error.kind.clone()).collect::<Vec<_>>(), vec![TraErrorKind::UnexpectedToken]);
 }
-*/
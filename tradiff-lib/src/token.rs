@@ -1,64 +1,106 @@
 use std::num::ParseIntError;
 
-use logos::Logos;
+use logos::{Lexer, Logos};
 
+use crate::tra_structs::Span;
+
+/// Some input that could not be turned into a token, the lexer skips it and goes on with the rest of the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    /// What was skipped
+    pub span: Span,
+}
 
 #[derive(Clone, Debug, PartialEq, Default)]
-pub enum LexError {
+pub enum LexErrorKind {
     IntegerOverflow,
     InvalidDigit,
     InvalidInteger(String),
-    InvalidToken,
+    /// Input that does not start any token
     #[default]
-    Unspecified,
+    InvalidToken,
+    /// `~~~~~` with no other `~~~~~` after it
+    UnclosedFiveTildes,
 }
 
-impl From<ParseIntError> for LexError {
+impl From<ParseIntError> for LexErrorKind {
     fn from(err: ParseIntError) -> Self {
         use std::num::IntErrorKind::*;
         match err.kind() {
-            PosOverflow | NegOverflow => LexError::IntegerOverflow,
-            InvalidDigit => LexError::InvalidDigit,
-            error => LexError::InvalidInteger(format!("{error:?}")),
+            PosOverflow | NegOverflow => LexErrorKind::IntegerOverflow,
+            InvalidDigit => LexErrorKind::InvalidDigit,
+            error => LexErrorKind::InvalidInteger(format!("{error:?}")),
+        }
+    }
+}
+
+impl std::fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LexErrorKind::IntegerOverflow => write!(f, "Number too large"),
+            LexErrorKind::InvalidDigit => write!(f, "Invalid digit in number"),
+            LexErrorKind::InvalidInteger(error) => write!(f, "Invalid number ({error})"),
+            LexErrorKind::InvalidToken => write!(f, "Unrecognized input"),
+            LexErrorKind::UnclosedFiveTildes => write!(f, "String opened with ~~~~~ but not closed"),
         }
     }
 }
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[ \t\n\f]+", error = LexError)]
+#[logos(skip r"[ \t\r\n\f]+", error = LexErrorKind)]
 pub enum Token {
 
     #[regex(r"@-?[0-9]+", |lex| lex.slice()[1..].parse())] // [0-9]+ because that's what weidu does
     Id(i64),
 
+    /// `@id =`, made by the lexer from an `Id` followed by `Equal`.
+    /// The parser resynchronises on it after an error.
+    EntryStart(i64),
+
     #[token("=")]
     Equal,
 
     #[token("^")]
     OperatorConcat,
 
-    #[regex(
-        r"~~~~~([^~]*|[^~]*~[^~]*|[^~]*~~[^~]*|[^~]*~~~[^~]*|[^~]*~~~~[^~]*)~~~~~",
-        |lex| { let s = &lex.slice(); s[5..s.len()-5].to_string() }
-    )]
+    #[token("~~~~~", five_tildes_string)]
     FiveTildesString(String),
-    #[regex(r"~[^~]*~", |lex| { let s = &lex.slice(); s[1..s.len()-1].to_string() })]
+    #[regex(r"~[^~]*~", |lex| { let s = &lex.slice(); normalize_line_breaks(&s[1..s.len()-1]) })]
     TildeString(String),
-    #[regex(r#""[^"]*""#, |lex| { let s = &lex.slice(); s[1..s.len()-1].to_string() })]
+    #[regex(r#""[^"]*""#, |lex| { let s = &lex.slice(); normalize_line_breaks(&s[1..s.len()-1]) })]
     DoubleQuoteString(String),
-    #[regex(r"%[^%]*%", |lex| { let s = &lex.slice(); s[1..s.len()-1].to_string() })]
+    #[regex(r"%[^%]*%", |lex| { let s = &lex.slice(); normalize_line_breaks(&s[1..s.len()-1]) })]
     PercentString(String),
 
-    #[regex("//[^\n]*\n", |lex| { let s = &lex.slice(); s[2..s.len() - 1].to_string() })]
+    #[regex("//[^\n]*\n", |lex| { let s = &lex.slice(); s[2..s.len() - 1].trim_end_matches('\r').to_string() })]
     EndOfLineComment(String),
-    #[regex(r"/\*([^\*]|\*[^/])*\*/", |lex| { let s = &lex.slice(); s[2..s.len() - 2].to_string() })]
+    #[regex(r"/\*([^\*]|\*[^/])*\*/", |lex| { let s = &lex.slice(); normalize_line_breaks(&s[2..s.len() - 2]) })]
     EnclosedComment(String),
 
     #[regex(r"\[[^\]]+\]", |lex| { let s = &lex.slice(); s[1..s.len()-1].to_string() })]
     SoundRef(String),
 
     #[regex(r"#-?[0-9]+", |lex| lex.slice()[1..].parse())]
-    TlkRef(u64),
+    TlkRef(i64),
+}
+
+/// Like weidu, a `~~~~~` string ends at the first `~~~~~` after the opening one, whatever comes before or after:
+/// - the content can contain up to four consecutive tildes, and start with tildes (`~~~~~~a~~~~~` is `~a`),
+/// - `~~~~~a~~~~~~~` is `a` followed by `~~`, an empty `~` string.
+///
+/// Five tildes win over `~` strings since they are longer, so `~~~~~~~~~~` is an empty `~~~~~` string.
+fn five_tildes_string(lex: &mut Lexer<Token>) -> Result<String, LexErrorKind> {
+    let remainder = lex.remainder();
+    let end = remainder.find("~~~~~").ok_or(LexErrorKind::UnclosedFiveTildes)?;
+    let content = normalize_line_breaks(&remainder[..end]);
+    lex.bump(end + "~~~~~".len());
+    Ok(content)
+}
+
+/// Windows line breaks are read as `\n`, so that contents do not depend on the platform the file was written on
+fn normalize_line_breaks(content: &str) -> String {
+    content.replace("\r\n", "\n")
 }
 
 impl std::fmt::Display for Token {
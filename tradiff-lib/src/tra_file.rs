@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use encoding_rs::Encoding;
+
+use crate::comments::{entry_comments, EntryComments};
+use crate::error::TraError;
+use crate::parsers::parse_trafile;
+use crate::tra_structs::{TraEntry, TraFragment};
+
+/// Why a TRA file could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// The parser could not recover from `error`
+    Parse {
+        error: Box<TraError>,
+        /// The errors found before, in file order
+        recovered: Vec<TraError>,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "could not read the file: {error}"),
+            LoadError::Parse { error, .. } => write!(f, "could not parse the file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            LoadError::Parse { error, .. } => Some(error.as_ref()),
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+/// A parsed TRA file, with its entries indexed by id.
+///
+/// All the entries are kept, including the duplicated ones. Lookups by id return the last definition,
+/// which is the one weidu uses.
+#[derive(Debug, Clone)]
+pub struct TraFile {
+    source: String,
+    charset_replacements: bool,
+    fragments: Vec<TraFragment>,
+    errors: Vec<TraError>,
+    /// The comments of each fragment, only entries have some
+    comments: Vec<EntryComments>,
+    /// Indexes in `fragments` of the entries of each id, in file order
+    by_id: BTreeMap<i64, Vec<usize>>,
+}
+
+impl TraFile {
+    pub fn parse(source: impl Into<String>) -> Result<TraFile, LoadError> {
+        let source = source.into();
+        let mut errors = Vec::new();
+        let result = parse_trafile(&mut errors, &source);
+        let fragments = match result {
+            Ok(fragments) => fragments,
+            Err(error) => return Err(LoadError::Parse { error, recovered: errors }),
+        };
+        let mut by_id = BTreeMap::<i64, Vec<usize>>::new();
+        for (index, fragment) in fragments.iter().enumerate() {
+            if let TraFragment::Entry(entry) = fragment {
+                by_id.entry(entry.id).or_default().push(index);
+            }
+        }
+        let comments = entry_comments(&source, fragments.iter());
+        Ok(TraFile { source, charset_replacements: false, fragments, errors, comments, by_id })
+    }
+
+    /// Decodes the file with `encoding`, a byte order mark is not removed
+    pub fn from_bytes(bytes: &[u8], encoding: &'static Encoding) -> Result<TraFile, LoadError> {
+        let (source, charset_replacements) = encoding.decode_without_bom_handling(bytes);
+        let mut file = TraFile::parse(source)?;
+        file.charset_replacements = charset_replacements;
+        Ok(file)
+    }
+
+    pub fn load(path: impl AsRef<Path>, encoding: &'static Encoding) -> Result<TraFile, LoadError> {
+        TraFile::from_bytes(&std::fs::read(path)?, encoding)
+    }
+
+    /// The decoded content of the file
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Some bytes could not be decoded with the encoding and were replaced with �
+    pub fn charset_replacements(&self) -> bool {
+        self.charset_replacements
+    }
+
+    /// Entries, comments and skipped parts, in file order
+    pub fn fragments(&self) -> &[TraFragment] {
+        &self.fragments
+    }
+
+    /// The errors the parser recovered from, in file order
+    pub fn errors(&self) -> &[TraError] {
+        &self.errors
+    }
+
+    /// All the entries, in file order
+    pub fn entries(&self) -> impl Iterator<Item = &TraEntry> {
+        self.fragments.iter().filter_map(TraFragment::as_entry)
+    }
+
+    /// All the entries sorted by id, the occurrences of a duplicated id are in file order
+    pub fn entries_by_id(&self) -> impl Iterator<Item = &TraEntry> {
+        self.by_id.values().flatten().map(|index| self.entry_at(*index))
+    }
+
+    /// The ids defined in the file, sorted and without duplicates
+    pub fn ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.by_id.keys().copied()
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    /// The last definition of `id`
+    pub fn get(&self, id: i64) -> Option<&TraEntry> {
+        self.by_id.get(&id).and_then(|indexes| indexes.last()).map(|index| self.entry_at(*index))
+    }
+
+    /// All the definitions of `id`, in file order
+    pub fn get_all(&self, id: i64) -> impl Iterator<Item = &TraEntry> {
+        self.by_id.get(&id).into_iter().flatten().map(|index| self.entry_at(*index))
+    }
+
+    /// The comments documenting the last definition of `id`: the comments just before it, without an empty
+    /// line between them, and the comments after it on the same line
+    pub fn comments(&self, id: i64) -> Option<&EntryComments> {
+        self.by_id.get(&id).and_then(|indexes| indexes.last()).map(|index| &self.comments[*index])
+    }
+
+    /// The comments documenting `entry`, one of the entries of this file (any definition of a duplicated id)
+    pub fn comments_of(&self, entry: &TraEntry) -> Option<&EntryComments> {
+        self.by_id.get(&entry.id)?.iter()
+            .find(|index| self.fragments[**index].span() == entry.span)
+            .map(|index| &self.comments[*index])
+    }
+
+    pub fn is_duplicated(&self, id: i64) -> bool {
+        self.by_id.get(&id).is_some_and(|indexes| indexes.len() > 1)
+    }
+
+    /// The ids defined more than once with their definitions in file order, sorted by id
+    pub fn duplicates(&self) -> impl Iterator<Item = (i64, Vec<&TraEntry>)> {
+        self.by_id.iter()
+            .filter(|(_, indexes)| indexes.len() > 1)
+            .map(|(id, indexes)| (*id, indexes.iter().map(|index| self.entry_at(*index)).collect()))
+    }
+
+    /// Ids of the entries skipped because of a syntax error, sorted and without duplicates
+    pub fn damaged_ids(&self) -> Vec<i64> {
+        let mut ids = self.fragments.iter()
+            .filter_map(|fragment| match fragment {
+                TraFragment::Error { id, .. } => *id,
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    fn entry_at(&self, index: usize) -> &TraEntry {
+        self.fragments[index].as_entry().expect("only entries are indexed")
+    }
+}
@@ -1,16 +1,38 @@
 
+/// Location of a parsed element, as byte offsets in the parsed text (`end` excluded).
+///
+/// Fragments compare equal only at the same location, use `same_content` to compare what they contain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TraEntry {
     pub id: i64,
     pub content: TraEntryContent,
-
+    /// From the `@id` to the end of the last string or sound reference
+    pub span: Span,
+    /// Comments between the parts of the entry, in file order
+    pub comments: Vec<(TraComment, Span)>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TraEntryContent {
     Explicit(ExplicitTraEntry),
     At(i64),
-    Tlk(u64),
+    Tlk(i64),
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -19,6 +41,35 @@ pub struct ExplicitTraEntry {
     pub sound: Option<String>,
     pub alt_value: Option<WeiduString>,
     pub alt_sound: Option<String>,
+    pub spans: ExplicitSpans,
+}
+
+/// Locations of the parts of an explicit entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExplicitSpans {
+    /// The whole value, including all the concatenated strings
+    pub value: Span,
+    pub sound: Option<Span>,
+    pub alt_value: Option<Span>,
+    pub alt_sound: Option<Span>,
+}
+
+impl TraEntry {
+    /// Same id, content and inner comments, wherever the entries are
+    pub fn same_content(&self, other: &TraEntry) -> bool {
+        self.id == other.id
+            && self.content.same_content(&other.content)
+            && self.comments.len() == other.comments.len()
+            && self.comments.iter().zip(&other.comments).all(|((comment, _), (other, _))| comment == other)
+    }
+}
+
+// Only used to build test expected values
+#[cfg(test)]
+impl TraEntry {
+    pub fn new(id: i64, content: TraEntryContent) -> Self {
+        TraEntry { id, content, span: Span::default(), comments: Vec::new() }
+    }
 }
 
 // Only used to build test expected values
@@ -49,8 +100,9 @@ impl ExplicitTraEntry {
         ExplicitTraEntry {
             value: WeiduString::Literal(value),
             sound: sound.map(|s| s.to_string()),
-            alt_value: alt_value.map(|s| WeiduString::Literal(s)),
+            alt_value: alt_value.map(WeiduString::Literal),
             alt_sound: alt_sound.map(|s|s.to_string()),
+            ..Default::default()
         }
     }
 }
@@ -69,12 +121,110 @@ pub enum WeiduStringLit {
     FiveTildes(String),
 }
 
+/// A string expression: strings and references, possibly concatenated with `^`
 #[derive(Debug, Clone, PartialEq)]
 pub enum WeiduString {
     Literal(WeiduStringLit),
+    /// Text of another entry, `@id`
     At(i64),
-    Ref(u32),
-    Concat(Box<WeiduString>, Box<WeiduStringLit>),
+    /// Text of dialog.tlk, `#strref`
+    Ref(i64),
+    /// Concatenations are nested on the left, the right side is never a concatenation
+    Concat(Box<WeiduString>, Box<WeiduString>),
+}
+
+impl WeiduStringLit {
+    /// The content of the string, without the delimiters
+    pub fn content(&self) -> &str {
+        match self {
+            WeiduStringLit::Tilde(content)
+            | WeiduStringLit::DoubleQuote(content)
+            | WeiduStringLit::Percent(content)
+            | WeiduStringLit::FiveTildes(content) => content,
+        }
+    }
+
+    /// A string with `content` and the same delimiters, or other delimiters when the content contains the
+    /// closing delimiter. `None` when no delimiters can hold the content.
+    pub fn with_content(&self, content: &str) -> Option<WeiduStringLit> {
+        let same = match self {
+            WeiduStringLit::Tilde(_) => WeiduStringLit::Tilde(content.to_string()),
+            WeiduStringLit::DoubleQuote(_) => WeiduStringLit::DoubleQuote(content.to_string()),
+            WeiduStringLit::Percent(_) => WeiduStringLit::Percent(content.to_string()),
+            WeiduStringLit::FiveTildes(_) => WeiduStringLit::FiveTildes(content.to_string()),
+        };
+        [
+            same,
+            WeiduStringLit::Tilde(content.to_string()),
+            WeiduStringLit::FiveTildes(content.to_string()),
+            WeiduStringLit::DoubleQuote(content.to_string()),
+            WeiduStringLit::Percent(content.to_string()),
+        ].into_iter().find(WeiduStringLit::is_writable)
+    }
+
+    /// The content does not contain the closing delimiter
+    fn is_writable(&self) -> bool {
+        match self {
+            WeiduStringLit::Tilde(content) => !content.contains('~'),
+            WeiduStringLit::DoubleQuote(content) => !content.contains('"'),
+            WeiduStringLit::Percent(content) => !content.contains('%'),
+            // a tilde at the end would be read as the first tilde of the closing delimiter
+            WeiduStringLit::FiveTildes(content) => !content.contains("~~~~~") && !content.ends_with('~'),
+        }
+    }
+}
+
+impl TraComment {
+    /// The content of the comment, without `//` or `/* */`
+    pub fn content(&self) -> &str {
+        match self {
+            TraComment::EndOfLine(content) | TraComment::Enclosed(content) => content,
+        }
+    }
+}
+
+impl WeiduString {
+    /// The text with concatenations applied.
+    /// References are not resolved and kept as `@id` or `#strref`.
+    pub fn text(&self) -> String {
+        match self {
+            WeiduString::Literal(lit) => lit.content().to_string(),
+            WeiduString::At(id) => format!("@{id}"),
+            WeiduString::Ref(strref) => format!("#{strref}"),
+            WeiduString::Concat(left, right) => left.text() + &right.text(),
+        }
+    }
+}
+
+impl TraEntryContent {
+    /// Same strings, references and sounds, wherever they are
+    pub fn same_content(&self, other: &TraEntryContent) -> bool {
+        match (self, other) {
+            (TraEntryContent::Explicit(explicit), TraEntryContent::Explicit(other)) =>
+                explicit.value == other.value
+                    && explicit.sound == other.sound
+                    && explicit.alt_value == other.alt_value
+                    && explicit.alt_sound == other.alt_sound,
+            _ => self == other,
+        }
+    }
+
+    /// The (male) text of the entry, references are kept as `@id` or `#strref`
+    pub fn text(&self) -> String {
+        match self {
+            TraEntryContent::Explicit(explicit) => explicit.value.text(),
+            TraEntryContent::At(id) => format!("@{id}"),
+            TraEntryContent::Tlk(strref) => format!("#{strref}"),
+        }
+    }
+
+    /// The female variant text of the entry, if any
+    pub fn female_text(&self) -> Option<String> {
+        match self {
+            TraEntryContent::Explicit(explicit) => explicit.alt_value.as_ref().map(WeiduString::text),
+            _ => None,
+        }
+    }
 }
 
 impl Default for WeiduString {
@@ -85,9 +235,14 @@ impl Default for WeiduString {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TraFragment {
-    Comment(TraComment),
+    Comment(TraComment, Span),
     Entry(TraEntry),
-    Error,
+    /// Input the parser skipped after a syntax error, up to the next `@id =`
+    Error {
+        span: Span,
+        /// The id of the broken entry, when the error is after its `@id =`
+        id: Option<i64>,
+    },
 }
 
 impl TraFragment {
@@ -97,6 +252,13 @@ impl TraFragment {
             _ => None,
         }
     }
+
+    pub fn span(&self) -> Span {
+        match self {
+            TraFragment::Comment(_, span) | TraFragment::Error { span, .. } => *span,
+            TraFragment::Entry(entry) => entry.span,
+        }
+    }
 }
 
 #[macro_export]